@@ -1,8 +1,11 @@
 use std::fmt;
-use std::string::ParseError;
+use std::cmp::Ordering;
+use std::ops::Bound::{self, Excluded, Included, Unbounded};
 use std::cmp::Ordering::{Less, Equal, Greater};
 use std::str::FromStr;
 
+use crate::error::RpkgError;
+
 pub enum VersionRelation {
     StrictlyLess, // <<
     LessOrEqual, // <=
@@ -24,7 +27,7 @@ impl fmt::Display for VersionRelation {
 }
 
 impl FromStr for VersionRelation {
-    type Err = ParseError;
+    type Err = RpkgError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
@@ -33,13 +36,13 @@ impl FromStr for VersionRelation {
             "=" =>  Ok(VersionRelation::Equal),
             ">=" => Ok(VersionRelation::GreaterOrEqual),
             ">>" => Ok(VersionRelation::StrictlyGreater),
-            _ => panic!("bad version relation {}", s) // gah this should be a ParseError but I don't know how to do that, accepting PRs
+            _ => Err(RpkgError::BadVersionRelation(s.to_string()))
         }
     }
 }
 
 
-#[derive(PartialEq,Eq)]
+#[derive(Clone)]
 pub struct DebianVersionNum {
     epoch : String,
     upstream : String,
@@ -54,10 +57,13 @@ impl fmt::Display for DebianVersionNum {
     }
 }
 
-impl FromStr for DebianVersionNum {
-    type Err = ParseError;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
+impl DebianVersionNum {
+    /// Splits s into epoch, upstream version and Debian revision without checking them against policy, for legacy data.
+    /// Only an empty version is rejected; a non-numeric epoch compares as 0.
+    pub fn parse_lenient(s: &str) -> Result<DebianVersionNum, RpkgError> {
+        if s.is_empty() {
+            return Err(bad_version(s, "empty version"));
+        }
         let (ep, rest0) = match s.find(':') {
             None => ("", s),
             Some(e) => { let (_e, _r) = s.split_at(e); (_e, &_r[1..]) }
@@ -72,112 +78,149 @@ impl FromStr for DebianVersionNum {
             debian: deb.to_string()
         })
     }
+
+    /// Checks s against the Debian policy rules for [epoch:]upstream_version[-debian_revision]
+    /// (https://www.debian.org/doc/debian-policy/ch-controlfields.html#version), and says which rule it breaks.
+    /// An upstream version should also start with a digit, but like dpkg we only warn about that; see warning.
+    pub fn validate(s: &str) -> Result<(), RpkgError> {
+        if s.is_empty() {
+            return Err(bad_version(s, "empty version"));
+        }
+        if let Some(c) = s.chars().find(|c| c.is_whitespace()) {
+            return Err(bad_version(s, &format!("version contains whitespace {:?}", c)));
+        }
+        let v = DebianVersionNum::parse_lenient(s)?;
+        if s.contains(':') {
+            if v.epoch.is_empty() {
+                return Err(bad_version(s, "epoch is empty"));
+            }
+            if !v.epoch.bytes().all(|c| c.is_ascii_digit()) {
+                return Err(bad_version(s, &format!("epoch {:?} is not a number", v.epoch)));
+            }
+            if v.epoch.parse::<i32>().is_err() {
+                return Err(bad_version(s, &format!("epoch {:?} is too big", v.epoch)));
+            }
+        }
+        if v.upstream.is_empty() {
+            return Err(bad_version(s, "upstream version is empty"));
+        }
+        if let Some(c) = v.upstream.chars().find(|c| !c.is_ascii_alphanumeric() && !".+-~".contains(*c)) {
+            return Err(bad_version(s, &format!("illegal character {:?} in upstream version {:?}", c, v.upstream)));
+        }
+        if s.ends_with('-') {
+            return Err(bad_version(s, "Debian revision is empty"));
+        }
+        if let Some(c) = v.debian.chars().find(|c| !c.is_ascii_alphanumeric() && !"+.~".contains(*c)) {
+            return Err(bad_version(s, &format!("illegal character {:?} in Debian revision {:?}", c, v.debian)));
+        }
+        Ok(())
+    }
+
+    /// Says which policy recommendation a version breaks, if any: those which, like dpkg, we warn about but accept.
+    /// For now there is just one, that the upstream version should start with a digit.
+    pub fn warning(s: &str) -> Option<String> {
+        let v = DebianVersionNum::parse_lenient(s).ok()?;
+        match v.upstream.chars().next() {
+            Some(c) if !c.is_ascii_digit() => Some(format!("upstream version {:?} does not start with a digit", v.upstream)),
+            _ => None
+        }
+    }
 }
 
-impl Ord for DebianVersionNum {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.partial_cmp(other).unwrap()
+fn bad_version(s: &str, reason: &str) -> RpkgError {
+    RpkgError::BadVersion { version : s.to_string(), reason : reason.to_string() }
+}
+
+impl FromStr for DebianVersionNum {
+    type Err = RpkgError;
+
+    /// Parses a policy-conforming version; see DebianVersionNum::validate and DebianVersionNum::parse_lenient.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        DebianVersionNum::validate(s)?;
+        DebianVersionNum::parse_lenient(s)
     }
 }
 
-impl PartialOrd for DebianVersionNum {
+impl Ord for DebianVersionNum {
     // https://www.debian.org/doc/debian-policy/ch-controlfields.html#version
-    // wow this is painful
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        fn extract_nonnum(s: &str) -> (&str,&str) {
-            let first_digit = s.find(|c:char| c.is_ascii_digit());
-            match first_digit {
-                None => (s, ""),
-                Some(fd) => {
-                    let (nonnum, rest) = s.split_at(fd);
-                    return (nonnum, rest);
-                }
-            }
-        }
-        fn extract_num(s: &str) -> (&str,&str) {
-            let first_nondigit = s.find(|c:char| !c.is_ascii_digit());
-            match first_nondigit {
-                None => (s, ""),
-                Some(fd) => {
-                    let (num, rest) = s.split_at(fd);
-                    return (num, rest);
-                }
-            }
-        }
+    // wow this is painful. This follows dpkg's verrevcmp() exactly.
+    fn cmp(&self, other: &Self) -> Ordering {
+        // a non-numeric epoch (lenient mode only) compares as 0, as does a missing one
+        let epoch = |e: &str| e.parse::<i32>().unwrap_or(0);
+        epoch(&self.epoch).cmp(&epoch(&other.epoch))
+            .then_with(|| verrevcmp(&self.upstream, &other.upstream))
+            .then_with(|| verrevcmp(&self.debian, &other.debian))
+    }
+}
 
-        fn to_debian_chars(s: &str) -> Vec<i32> {
-            let mut v = vec![];
-            // all the letters sort earlier than all the non-letters and so that a tilde sorts before anything, even the end of a part
-            // . + - ~
-            for c in s.bytes() {
-                let cc:i32 = match c {
-                    46 /* '.' */ => 256+46,
-                    43 /* '+' */ => 256+43,
-                    45 /* '-' */ => 256+45,
-                    126 /* '~' */ => -1,
-                    _ => i32::from(c) };
-                v.push(cc);
-            }
-            v
-        }
+impl PartialOrd for DebianVersionNum {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
 
-        fn debian_nonnum_cmp(s: &str, o: &str) -> std::cmp::Ordering {
-            let (d_s, d_o) = (to_debian_chars(s), to_debian_chars(o));
-            for it in d_s.iter().zip(d_o.iter()) {
-                let (c_s, c_o) = it;
-                if c_s < c_o { return Less; }
-                if c_s > c_o { return Greater; }
-            }
-            // aa < aaa
-            if s.len() < o.len() { return Less; }
-            // aa~ < aa
-            if s.len() > o.len() && s.ends_with('~') { return Less; }
-            // aaa > aa
-            if s.len() > o.len() { return Greater; }
-            Equal
-        }
-
-        fn debian_cmp(self_vers: &str, other_vers: &str) -> std::cmp::Ordering {
-            let mut sv = self_vers;
-            let mut ov = other_vers;
-            loop {
-                if sv.is_empty() && ov.is_empty() {
-                    return Equal;
-                }
-                let (self_nonnum, self_rest) = extract_nonnum(sv);
-                let (other_nonnum, other_rest) = extract_nonnum(ov);
-                match debian_nonnum_cmp(self_nonnum, other_nonnum) {
-                    Less => return Less,
-                    Greater => return Greater,
-                    _ => ()
-                }
+// versions which differ only in spelling, like 1.0 and 1.00, or 1.0 and 0:1.0-0, are the same version
+impl PartialEq for DebianVersionNum {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Equal
+    }
+}
 
-                let (self_num, self_rest1) = extract_num(self_rest);
-                let (other_num, other_rest1) = extract_num(other_rest);
+impl Eq for DebianVersionNum {}
 
-                let (sn_i, on_i) = (
-                    match self_num.parse::<i32>() { Err(_) => 0, Ok(e) => e },
-                    match other_num.parse::<i32>() { Err(_) => 0, Ok(e) => e });
+/// The sort weight of a character in a non-digit part: the end of the part (None) and digits weigh 0,
+/// letters sort before everything else, and a tilde sorts before anything, even the end of the part.
+fn order(c: Option<u8>) -> i32 {
+    match c {
+        None => 0,
+        Some(c) if c.is_ascii_digit() => 0,
+        Some(c) if c.is_ascii_alphabetic() => i32::from(c),
+        Some(b'~') => -1,
+        Some(c) => i32::from(c) + 256
+    }
+}
 
-                if sn_i != on_i {
-                    return sn_i.partial_cmp(&on_i).unwrap();
-                }
-                sv = self_rest1; ov = other_rest1;
+/// Compares two upstream versions or two Debian revisions: alternately, the longest initial non-digit parts,
+/// character by character, and the longest initial digit parts, as numbers of any length.
+fn verrevcmp(a: &str, b: &str) -> Ordering {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    let (mut i, mut j) = (0, 0);
+    let is_digit = |s: &[u8], k: usize| s.get(k).is_some_and(|c| c.is_ascii_digit());
+    while i < a.len() || j < b.len() {
+        while (i < a.len() && !is_digit(a, i)) || (j < b.len() && !is_digit(b, j)) {
+            let (ac, bc) = (order(a.get(i).copied()), order(b.get(j).copied()));
+            if ac != bc {
+                return ac.cmp(&bc);
             }
+            i += 1;
+            j += 1;
         }
-
-        let (epoch, other_epoch) = (
-            match self.epoch.parse::<i32>() { Err(_) => 0, Ok(e) => e },
-            match other.epoch.parse::<i32>() { Err(_) => 0, Ok(e) => e });
-        if epoch != other_epoch {
-            return Some(epoch.partial_cmp(&other_epoch).unwrap());
+        while a.get(i) == Some(&b'0') {
+            i += 1;
+        }
+        while b.get(j) == Some(&b'0') {
+            j += 1;
+        }
+        // without leading zeros, the longer number is bigger; otherwise the first differing digit decides
+        let mut first_diff = Equal;
+        while is_digit(a, i) && is_digit(b, j) {
+            if first_diff == Equal {
+                first_diff = a[i].cmp(&b[j]);
+            }
+            i += 1;
+            j += 1;
         }
-        let ups = debian_cmp(&self.upstream, &other.upstream);
-        if ups != Equal {
-            return Some(ups);
+        if is_digit(a, i) {
+            return Greater;
+        }
+        if is_digit(b, j) {
+            return Less;
+        }
+        if first_diff != Equal {
+            return first_diff;
         }
-        return Some(debian_cmp(&self.debian, &other.debian));
     }
+    Equal
 }
 
 pub fn cmp_debversion_with_op(op:&VersionRelation, first: &DebianVersionNum, second: &DebianVersionNum) -> bool {
@@ -189,3 +232,165 @@ pub fn cmp_debversion_with_op(op:&VersionRelation, first: &DebianVersionNum, sec
         VersionRelation::StrictlyGreater => first > second
     }
 }
+
+/// A set of versions, such as ">= 1.2" or ">= 1.2, << 2.0", kept as a sorted list of disjoint, non-empty intervals
+/// so that constraints can be intersected (both must hold) and united (either may hold).
+/// Between any two different versions there is (for all practical purposes) another one, e.g. 1.0 < 1.0a~ < 1.0a,
+/// so an interval is only empty when its bounds cross, or touch without both being included.
+#[derive(Clone)]
+pub struct VersionConstraint {
+    intervals : Vec<(Bound<DebianVersionNum>, Bound<DebianVersionNum>)>
+}
+
+impl VersionConstraint {
+    /// Every version.
+    pub fn any() -> VersionConstraint {
+        VersionConstraint { intervals : vec![(Unbounded, Unbounded)] }
+    }
+
+    /// No version at all.
+    pub fn none() -> VersionConstraint {
+        VersionConstraint { intervals : vec![] }
+    }
+
+    /// The versions v such that "v op version" holds, e.g. (>=, 1.2) is ">= 1.2".
+    pub fn new(op: VersionRelation, version: DebianVersionNum) -> VersionConstraint {
+        let interval = match op {
+            VersionRelation::StrictlyLess =>    (Unbounded, Excluded(version)),
+            VersionRelation::LessOrEqual =>     (Unbounded, Included(version)),
+            VersionRelation::Equal =>           (Included(version.clone()), Included(version)),
+            VersionRelation::GreaterOrEqual =>  (Included(version), Unbounded),
+            VersionRelation::StrictlyGreater => (Excluded(version), Unbounded)
+        };
+        VersionConstraint { intervals : vec![interval] }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.intervals.is_empty()
+    }
+
+    pub fn satisfied_by(&self, version: &DebianVersionNum) -> bool {
+        self.intervals.iter().any(|(lower, upper)| {
+            (match lower { Unbounded => true, Included(l) => l <= version, Excluded(l) => l < version }) &&
+                (match upper { Unbounded => true, Included(u) => version <= u, Excluded(u) => version < u })
+        })
+    }
+
+    /// The single version this constraint allows, if it's of the form "= v".
+    pub fn exact_version(&self) -> Option<&DebianVersionNum> {
+        match self.intervals.as_slice() {
+            [(Included(l), Included(u))] if l == u => Some(l),
+            _ => None
+        }
+    }
+
+    /// The versions satisfying both self and other.
+    pub fn intersection(&self, other: &VersionConstraint) -> VersionConstraint {
+        let mut intervals = vec![];
+        for (l1, u1) in &self.intervals {
+            for (l2, u2) in &other.intervals {
+                let lower = if cmp_lower(l1, l2) == Greater { l1 } else { l2 };
+                let upper = if cmp_upper(u1, u2) == Less { u1 } else { u2 };
+                if interval_is_nonempty(lower, upper) {
+                    intervals.push((lower.clone(), upper.clone()));
+                }
+            }
+        }
+        VersionConstraint::normalized(intervals)
+    }
+
+    /// The versions satisfying self or other (or both).
+    pub fn union(&self, other: &VersionConstraint) -> VersionConstraint {
+        VersionConstraint::normalized(self.intervals.iter().chain(other.intervals.iter()).cloned().collect())
+    }
+
+    /// Sorts non-empty intervals by lower bound and merges the ones which overlap or touch.
+    fn normalized(mut intervals: Vec<(Bound<DebianVersionNum>, Bound<DebianVersionNum>)>) -> VersionConstraint {
+        intervals.sort_by(|(l1, _), (l2, _)| cmp_lower(l1, l2));
+        let mut merged : Vec<(Bound<DebianVersionNum>, Bound<DebianVersionNum>)> = vec![];
+        for (lower, upper) in intervals {
+            match merged.last_mut() {
+                Some((_, last_upper)) if reaches(last_upper, &lower) => {
+                    if cmp_upper(&upper, last_upper) == Greater {
+                        *last_upper = upper;
+                    }
+                }
+                _ => merged.push((lower, upper))
+            }
+        }
+        VersionConstraint { intervals : merged }
+    }
+}
+
+// lower bounds: Unbounded < Included(v) < Excluded(v)
+fn cmp_lower(a: &Bound<DebianVersionNum>, b: &Bound<DebianVersionNum>) -> Ordering {
+    match (a, b) {
+        (Unbounded, Unbounded) => Equal,
+        (Unbounded, _) => Less,
+        (_, Unbounded) => Greater,
+        (Included(x), Included(y)) | (Excluded(x), Excluded(y)) => x.cmp(y),
+        (Included(x), Excluded(y)) => x.cmp(y).then(Less),
+        (Excluded(x), Included(y)) => x.cmp(y).then(Greater)
+    }
+}
+
+// upper bounds: Excluded(v) < Included(v) < Unbounded
+fn cmp_upper(a: &Bound<DebianVersionNum>, b: &Bound<DebianVersionNum>) -> Ordering {
+    match (a, b) {
+        (Unbounded, Unbounded) => Equal,
+        (Unbounded, _) => Greater,
+        (_, Unbounded) => Less,
+        (Included(x), Included(y)) | (Excluded(x), Excluded(y)) => x.cmp(y),
+        (Included(x), Excluded(y)) => x.cmp(y).then(Greater),
+        (Excluded(x), Included(y)) => x.cmp(y).then(Less)
+    }
+}
+
+fn interval_is_nonempty(lower: &Bound<DebianVersionNum>, upper: &Bound<DebianVersionNum>) -> bool {
+    match (lower, upper) {
+        (Unbounded, _) | (_, Unbounded) => true,
+        (Included(l), Included(u)) => l <= u,
+        (Included(l), Excluded(u)) | (Excluded(l), Included(u)) | (Excluded(l), Excluded(u)) => l < u
+    }
+}
+
+// whether an interval ending at upper overlaps or touches one starting at lower (which doesn't start before it),
+// so that the two can be merged
+fn reaches(upper: &Bound<DebianVersionNum>, lower: &Bound<DebianVersionNum>) -> bool {
+    match (upper, lower) {
+        (Unbounded, _) | (_, Unbounded) => true,
+        (Excluded(u), Excluded(l)) => l < u,
+        (Included(u), Included(l)) | (Included(u), Excluded(l)) | (Excluded(u), Included(l)) => l <= u
+    }
+}
+
+impl fmt::Display for VersionConstraint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.intervals.is_empty() {
+            return write!(f, "none");
+        }
+        for (i, (lower, upper)) in self.intervals.iter().enumerate() {
+            if i > 0 {
+                write!(f, " | ")?;
+            }
+            match (lower, upper) {
+                (Unbounded, Unbounded) => write!(f, "any")?,
+                (Included(l), Included(u)) if l == u => write!(f, "= {}", l)?,
+                _ => {
+                    match lower {
+                        Unbounded => (),
+                        Included(l) => write!(f, ">= {}", l)?,
+                        Excluded(l) => write!(f, ">> {}", l)?
+                    }
+                    let sep = if let Unbounded = lower { "" } else { ", " };
+                    match upper {
+                        Unbounded => (),
+                        Included(u) => write!(f, "{}<= {}", sep, u)?,
+                        Excluded(u) => write!(f, "{}<< {}", sep, u)?
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,73 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Everything that can go wrong while loading package data or looking things up in it.
+#[derive(Debug)]
+pub enum RpkgError {
+    /// couldn't open or read a file
+    Io { file : String, source : io::Error },
+    /// the CSV reader couldn't read a record
+    Csv { file : String, source : csv::Error },
+    /// a field in a Packages/installed/CSV file didn't parse; line numbers start at 1
+    Parse { file : String, line : usize, field : String, source : Box<RpkgError> },
+    /// a version relation other than <<, <=, =, >=, >>
+    BadVersionRelation(String),
+    /// a version number that can't be parsed
+    BadVersion { version : String, reason : String },
+    /// a field whose value isn't one of those it may have, like Multi-Arch: sam
+    BadField { value : String, expected : String },
+    /// a record which is missing a field, e.g. a short CSV row
+    MissingField(String),
+    /// a package name that doesn't appear in any loaded file
+    UnknownPackage(String),
+    /// a package which would have to be installed for the command to make sense
+    NotInstalled(String),
+    /// the solver couldn't find a consistent set of packages; the string explains why
+    Unsatisfiable(String),
+    /// a search pattern that isn't a valid regular expression
+    BadPattern { pattern : String, source : regex::Error },
+    /// a stanza of a preferences file that rpkg can't use, like an unsupported pin
+    BadPreference(String),
+    /// an index file of a repository which doesn't match what its Release file says about it
+    BadIndex { file : String, reason : String }
+}
+
+impl RpkgError {
+    /// Wraps self with the location (file, line, field) at which it happened.
+    pub fn at(self, file: &str, line: usize, field: &str) -> RpkgError {
+        RpkgError::Parse { file : file.to_string(), line, field : field.to_string(), source : Box::new(self) }
+    }
+}
+
+impl fmt::Display for RpkgError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RpkgError::Io { file, source } =>                 write!(f, "{}: {}", file, source),
+            RpkgError::Csv { file, source } =>                write!(f, "{}: {}", file, source),
+            RpkgError::Parse { file, line, field, source } => write!(f, "{}:{}: field {}: {}", file, line, field, source),
+            RpkgError::BadVersionRelation(r) =>               write!(f, "bad version relation {:?}", r),
+            RpkgError::BadVersion { version, reason } =>      write!(f, "bad version {:?}: {}", version, reason),
+            RpkgError::BadField { value, expected } =>        write!(f, "bad value {:?}, expected {}", value, expected),
+            RpkgError::MissingField(field) =>                 write!(f, "missing field {}", field),
+            RpkgError::UnknownPackage(p) =>                   write!(f, "no such package {}", p),
+            RpkgError::NotInstalled(p) =>                     write!(f, "package {} is not installed", p),
+            RpkgError::Unsatisfiable(why) =>                  write!(f, "{}", why),
+            RpkgError::BadPattern { pattern, source } =>      write!(f, "bad pattern {:?}: {}", pattern, source),
+            RpkgError::BadPreference(why) =>                  write!(f, "bad preference: {}", why),
+            RpkgError::BadIndex { file, reason } =>           write!(f, "{}: {}", file, reason)
+        }
+    }
+}
+
+impl Error for RpkgError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RpkgError::Io { source, .. } => Some(source),
+            RpkgError::Csv { source, .. } => Some(source),
+            RpkgError::Parse { source, .. } => Some(source.as_ref()),
+            RpkgError::BadPattern { source, .. } => Some(source),
+            _ => None
+        }
+    }
+}
@@ -1 +1,2 @@
 pub mod debversion;
+pub mod error;
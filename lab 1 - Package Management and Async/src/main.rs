@@ -1,8 +1,8 @@
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
 
-use rpkg::debversion;
-use crate::packages::Packages;
+use rpkg::error::RpkgError;
+use crate::packages::{Packages, SearchOptions};
 
 mod packages;
 
@@ -15,11 +15,20 @@ fn check_syntax(n: usize, cmd_fragments:&Vec<&str>, arg: &str) -> bool {
     return true
 }
 
+// like check_syntax, for commands which take any number of packages
+fn check_syntax_at_least(n: usize, cmd_fragments: &[&str], arg: &str) -> bool {
+    if cmd_fragments.len() < n {
+        println!("syntax: {} {}", cmd_fragments[0], arg);
+        return false
+    }
+    true
+}
+
 fn process_command(state: &mut Packages, cmdline: &str) -> bool {
     let cmd_fragments: Vec<&str> = cmdline.split(" ").collect();
     if cmdline.is_empty() { return false }
     let cmd : &str = &cmd_fragments.get(0).unwrap();
-    match cmd {
+    let result : Result<(), RpkgError> = match cmd {
         "quit" => { 
             return true 
         },
@@ -41,8 +50,14 @@ fn process_command(state: &mut Packages, cmdline: &str) -> bool {
         }
         // convenience function, also depends on parsers.rs
         "load-defaults" | "ld" => {
-            state.parse_packages("data/mirror.csclub.uwaterloo.ca_debian_dists_sid_main_binary-amd64_Packages");
-            state.parse_installed("data/installed-packages")
+            state.parse_packages("data/mirror.csclub.uwaterloo.ca_debian_dists_sid_main_binary-amd64_Packages")
+                .and_then(|_| state.parse_installed("data/installed-packages"))
+        }
+        // repo.rs
+        "load-repo" => {
+            if !check_syntax_at_least(2, &cmd_fragments, "<dir> [<suite>...]") { return false; }
+            let dir = cmd_fragments.get(1).unwrap();
+            state.load_repo(dir, &cmd_fragments[2..])
         }
 
         "info" => {
@@ -50,6 +65,16 @@ fn process_command(state: &mut Packages, cmdline: &str) -> bool {
             let pkg = cmd_fragments.get(1).unwrap();
             state.print_info(pkg)
         }
+        "conflicts" => {
+            if !check_syntax(2, &cmd_fragments, "<pkg>") { return false; }
+            let pkg = cmd_fragments.get(1).unwrap();
+            state.print_conflicts(pkg)
+        }
+        "providers" => {
+            if !check_syntax(2, &cmd_fragments, "<virtual-pkg>") { return false; }
+            let pkg = cmd_fragments.get(1).unwrap();
+            state.print_providers(pkg)
+        }
         "deps" => {
             // test: deps 0ad
             if !check_syntax(2, &cmd_fragments, "<pkg>") { return false; }
@@ -60,57 +85,172 @@ fn process_command(state: &mut Packages, cmdline: &str) -> bool {
         // deps-available.rs
         "deps-available" => {
             // test: deps-available 3depict
+            if !check_syntax_at_least(2, &cmd_fragments, "<pkg>...") { return false; }
+            state.deps_available_all(&cmd_fragments[1..])
+        }
+
+        // rdepends.rs
+        "rdepends" => {
+            let recursive = cmd_fragments.contains(&"--recursive");
+            let installed_only = cmd_fragments.contains(&"--installed");
+            let n = 2 + recursive as usize + installed_only as usize;
+            if !check_syntax(n, &cmd_fragments, "[--recursive] [--installed] <pkg>") { return false; }
+            let pkg = cmd_fragments.get(n - 1).unwrap();
+            if recursive {
+                state.print_rdepends_recursive(pkg, installed_only)
+            } else {
+                state.print_rdepends(pkg, installed_only)
+            }
+        }
+
+        // removal.rs
+        "how-to-remove" => {
             if !check_syntax(2, &cmd_fragments, "<pkg>") { return false; }
             let pkg = cmd_fragments.get(1).unwrap();
-            state.deps_available(pkg)
+            state.print_how_to_remove(pkg)
+        }
+        "autoremove" => {
+            if !check_syntax(1, &cmd_fragments, "") { return false; }
+            state.print_autoremove();
+            Ok(())
         }
 
-        // solvers.rs, and deps-available.rs for how-to-install
-        "transitive-dep-solution" => {
-            // test: transitive-dep-solution 0ad
+        // search.rs
+        "search" => {
+            let mut options = SearchOptions::default();
+            let (flags, patterns) : (Vec<&str>, Vec<&str>) = cmd_fragments[1..].iter().partition(|f| f.starts_with("--"));
+            if patterns.len() != 1 || !flags.iter().all(|flag| options.set_flag(flag)) {
+                println!("syntax: {} [{}] <pattern>", cmd, SearchOptions::FLAGS.join("] ["));
+                return false
+            }
+            state.print_search(patterns[0], &options)
+        }
+
+        // upgrade.rs
+        "list-upgradable" => {
+            if !check_syntax(1, &cmd_fragments, "") { return false; }
+            state.print_upgradable();
+            Ok(())
+        }
+        "upgrade" | "dist-upgrade" => {
+            if !check_syntax(1, &cmd_fragments, "") { return false; }
+            state.print_upgrade(cmd == "dist-upgrade");
+            Ok(())
+        }
+
+        // transactions.rs
+        "install" => {
+            let install_recommends = cmd_fragments.get(1) == Some(&"--install-recommends");
+            let n = if install_recommends { 3 } else { 2 };
+            if !check_syntax(n, &cmd_fragments, "[--install-recommends] <pkg>") { return false; }
+            let pkg = cmd_fragments.get(n - 1).unwrap();
+            state.install(pkg, install_recommends)
+        }
+        "remove" => {
             if !check_syntax(2, &cmd_fragments, "<pkg>") { return false; }
             let pkg = cmd_fragments.get(1).unwrap();
-            state.print_transitive_dep_solution(pkg)
+            state.remove(pkg)
         }
-        "how-to-install" => {
+        "rollback" => {
+            if !check_syntax(1, &cmd_fragments, "") { return false; }
+            state.rollback();
+            Ok(())
+        }
+        "save-installed" => {
+            if !check_syntax(2, &cmd_fragments, "<output-file>") { return false; }
+            let fname = cmd_fragments.get(1).unwrap();
+            state.save_installed(fname)
+        }
+
+        // graph.rs
+        "graph" => {
+            if !check_syntax(3, &cmd_fragments, "<pkg> <output-file>") { return false; }
+            let pkg = cmd_fragments.get(1).unwrap();
+            let fname = cmd_fragments.get(2).unwrap();
+            state.write_dependency_graph(pkg, fname)
+        }
+
+        // why.rs
+        "why" => {
+            if !check_syntax(3, &cmd_fragments, "<pkg> <dep>") { return false; }
+            let pkg = cmd_fragments.get(1).unwrap();
+            let dep = cmd_fragments.get(2).unwrap();
+            state.print_why(pkg, dep)
+        }
+        "why-not" => {
+            if !check_syntax(2, &cmd_fragments, "<pkg>") { return false; }
+            let pkg = cmd_fragments.get(1).unwrap();
+            state.print_why_not(pkg)
+        }
+
+        // preferences.rs
+        "load-preferences" => {
+            if !check_syntax(2, &cmd_fragments, "<preferences-file>") { return false; }
+            let fname = cmd_fragments.get(1).unwrap();
+            state.parse_preferences(fname)
+        }
+        "policy" => {
             if !check_syntax(2, &cmd_fragments, "<pkg>") { return false; }
             let pkg = cmd_fragments.get(1).unwrap();
-            state.print_how_to_install(pkg)
+            state.print_policy(pkg)
         }
 
+        // solvers.rs, and deps-available.rs for how-to-install
+        "transitive-dep-solution" => {
+            // test: transitive-dep-solution 0ad
+            if !check_syntax_at_least(2, &cmd_fragments, "<pkg>...") { return false; }
+            state.print_transitive_dep_solution(&cmd_fragments[1..])
+        }
+        "how-to-install" => {
+            let install_recommends = cmd_fragments.get(1) == Some(&"--install-recommends");
+            let n = if install_recommends { 3 } else { 2 };
+            if !check_syntax_at_least(n, &cmd_fragments, "[--install-recommends] <pkg>...") { return false; }
+            state.print_how_to_install(&cmd_fragments[n - 1..], install_recommends)
+        }
+
+        "set-arch" => {
+            if cmd_fragments.len() < 2 {
+                state.print_arch();
+                return false
+            }
+            let native = cmd_fragments.get(1).unwrap();
+            state.set_arch(native, &cmd_fragments[2..]);
+            Ok(())
+        }
+        "set-version-mode" => {
+            match cmd_fragments.get(1) {
+                None => state.print_version_mode(),
+                Some(&"strict") => state.set_version_mode(false),
+                Some(&"lenient") => state.set_version_mode(true),
+                Some(_) => println!("syntax: {} [strict|lenient]", cmd)
+            }
+            Ok(())
+        }
         "set-server" => {
             if !check_syntax(2, &cmd_fragments, "<server>") { return false; }
             let server = cmd_fragments.get(1).unwrap();
-            state.set_server(server)
+            state.set_server(server);
+            Ok(())
         }
         "execute" => {
             state.execute();
+            Ok(())
         }
         "enq-verify" => {
-            let cmd : &str = &cmd_fragments.get(0).unwrap();
-            if cmd_fragments.len() < 2 || cmd_fragments.len() > 3 {
-                println!("syntax: {} <pkg> [<version>]", cmd);
-                return false
-            }
-            let pkg = cmd_fragments.get(1).unwrap();
-            if cmd_fragments.len() == 2 {
-                state.enq_verify(pkg);
-            } else {
-                let version = cmd_fragments.get(2).unwrap();
-                state.enq_verify_with_version(pkg, version);
-            }
+            if !check_syntax_at_least(2, &cmd_fragments, "<pkg>[=<version>]... | <pkg> <version>") { return false; }
+            state.enq_verify_all(&cmd_fragments[1..])
         }
 
         "output-md5s" => {
-            if !check_syntax(2, &cmd_fragments, "<output-file>") { return false; }
+            let with_sha256 = cmd_fragments.get(2) == Some(&"--sha256");
+            if !check_syntax(2 + with_sha256 as usize, &cmd_fragments, "<output-file> [--sha256]") { return false; }
             let fname = cmd_fragments.get(1).unwrap();
-            state.output_md5s(fname);
+            state.output_md5s(fname, with_sha256)
         }
         "test-version-compare" => {
             if !check_syntax(3, &cmd_fragments, "<version1> <version2>") { return false; }
-            let v1 = cmd_fragments.get(1).unwrap().parse::<debversion::DebianVersionNum>().unwrap();
-            let v2 = cmd_fragments.get(2).unwrap().parse::<debversion::DebianVersionNum>().unwrap();
-            println!("{} and {}: {:?}", v1, v2, v1.cmp(&v2));
+            let (v1, v2) = (cmd_fragments.get(1).unwrap(), cmd_fragments.get(2).unwrap());
+            state.print_version_compare(v1, v2)
             // 1:0.4.5+cvs20030824-9 vs 1:0.4.5+cvs20030824-10
             // a vs b
             // a vs a
@@ -120,8 +260,12 @@ fn process_command(state: &mut Packages, cmdline: &str) -> bool {
             // a vs ~a
         }
         _ => {
-            println!("couldn't understand cmd {:?}", cmd)
+            println!("couldn't understand cmd {:?}", cmd);
+            Ok(())
         }
+    };
+    if let Err(e) = result {
+        println!("{}", e);
     }
     return false;
 }
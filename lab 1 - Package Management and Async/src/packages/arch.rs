@@ -0,0 +1,168 @@
+use std::fmt;
+use std::str::FromStr;
+
+use itertools::Itertools;
+
+use rpkg::error::RpkgError;
+
+use crate::Packages;
+use crate::packages::{Dependency, RelVersionedPackageNum};
+
+pub const DEFAULT_NATIVE_ARCH : &str = "amd64";
+
+/// The Multi-Arch field: how a package interacts with packages of other architectures.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum MultiArch {
+    No,
+    Same, // co-installable with itself for other architectures
+    Foreign, // satisfies dependencies from packages of any architecture
+    Allowed // satisfies dependencies from packages of any architecture if they ask for it, with pkg:any
+}
+
+impl fmt::Display for MultiArch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MultiArch::No =>      write!(f, "no"),
+            MultiArch::Same =>    write!(f, "same"),
+            MultiArch::Foreign => write!(f, "foreign"),
+            MultiArch::Allowed => write!(f, "allowed")
+        }
+    }
+}
+
+impl FromStr for MultiArch {
+    type Err = RpkgError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "no" => Ok(MultiArch::No),
+            "same" => Ok(MultiArch::Same),
+            "foreign" => Ok(MultiArch::Foreign),
+            "allowed" => Ok(MultiArch::Allowed),
+            _ => Err(RpkgError::BadField { value : s.to_string(), expected : String::from("no, same, foreign or allowed") })
+        }
+    }
+}
+
+impl Packages {
+    /// Sets the native architecture and the foreign architectures we're also willing to install packages for.
+    pub fn set_arch(&mut self, native: &str, foreign: &[&str]) {
+        self.native_arch = native.to_string();
+        self.foreign_archs = foreign.iter().map(|a| a.to_string()).collect();
+        self.print_arch();
+    }
+
+    pub fn print_arch(&self) {
+        println!("Native architecture: {}", self.native_arch);
+        println!("Foreign architectures: {}", self.foreign_archs.iter().format(", "));
+    }
+
+    /// Returns true if an alternative's architecture restriction list ([amd64 !i386]) includes the native architecture.
+    /// Per policy, a list is either all positive or all negated.
+    pub fn alternative_applies(&self, alt: &RelVersionedPackageNum) -> bool {
+        if alt.arch_restrictions.is_empty() {
+            return true;
+        }
+        let listed = alt.arch_restrictions.iter().any(|(_, a)| *a == self.native_arch);
+        if alt.arch_restrictions.iter().all(|(negated, _)| *negated) { !listed } else { listed }
+    }
+
+    /// Returns true if some alternative of dd applies on the native architecture; otherwise dd can be ignored.
+    pub fn dependency_applies(&self, dd: &Dependency) -> bool {
+        dd.iter().any(|alt| self.alternative_applies(alt))
+    }
+
+    /// Returns true if package_num can be installed at all, i.e. it's for the native architecture, a foreign
+    /// architecture we've enabled, or "all". Packages of unknown architecture are assumed to be fine.
+    pub fn arch_installable(&self, package_num: i32) -> bool {
+        match self.architectures.get(&package_num) {
+            None => true,
+            Some(a) => a == "all" || *a == self.native_arch || self.foreign_archs.contains(a)
+        }
+    }
+
+    /// Returns true if package_num's architecture lets it satisfy alternative alt of a dependency of a native package:
+    ///  - pkg (no qualifier) and pkg:native need a native or "all" package, unless (for no qualifier) it's Multi-Arch: foreign;
+    ///  - pkg:any needs a Multi-Arch: allowed (or foreign) package, of any installable architecture;
+    ///  - pkg:arch needs a package for that architecture (or "all").
+    pub fn arch_satisfies(&self, alt: &RelVersionedPackageNum, package_num: i32) -> bool {
+        if !self.alternative_applies(alt) || !self.arch_installable(package_num) {
+            return false;
+        }
+        let arch = match self.architectures.get(&package_num) {
+            None => return true,
+            Some(a) => a
+        };
+        let multi_arch = self.multi_arch.get(&package_num).unwrap_or(&MultiArch::No);
+        let native_or_all = arch == "all" || *arch == self.native_arch;
+        match alt.arch_qualifier.as_deref() {
+            None => native_or_all || *multi_arch == MultiArch::Foreign,
+            Some("native") => native_or_all,
+            Some("any") => *multi_arch == MultiArch::Allowed || *multi_arch == MultiArch::Foreign,
+            Some(q) => arch == "all" || arch == q
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Packages;
+    use crate::packages::RelVersionedPackageNum;
+    use super::MultiArch;
+
+    /// The alternative of app's dependencies which is on package_name.
+    fn alt<'a>(p: &'a Packages, package_name: &str) -> &'a RelVersionedPackageNum {
+        let package_num = p.get_package_num(package_name).unwrap();
+        p.dependencies[&p.get_package_num("app").unwrap()].iter().flatten().find(|alt| alt.package_num == package_num).unwrap()
+    }
+
+    #[test]
+    fn parses_qualifiers_and_restriction_lists() {
+        let p = Packages::load_fixture("arch/multiarch");
+        assert_eq!(alt(&p, "tool").arch_qualifier.as_deref(), Some("any"));
+        assert_eq!(alt(&p, "plugin").arch_qualifier.as_deref(), Some("native"));
+        assert_eq!(alt(&p, "libfoo").arch_qualifier.as_deref(), Some("i386"));
+        assert_eq!(alt(&p, "helper").arch_qualifier, None);
+        assert_eq!(alt(&p, "winonly").arch_restrictions, vec![(false, String::from("win32"))]);
+        assert_eq!(alt(&p, "linuxthing").arch_restrictions, vec![(true, String::from("win32"))]);
+    }
+
+    #[test]
+    fn restriction_lists_follow_the_native_architecture() {
+        let mut p = Packages::load_fixture("arch/multiarch");
+        assert!(!p.alternative_applies(alt(&p, "winonly")));
+        assert!(p.alternative_applies(alt(&p, "linuxthing")));
+        assert!(p.alternative_applies(alt(&p, "helper")));
+        p.set_arch("win32", &[]);
+        assert!(p.alternative_applies(alt(&p, "winonly")));
+        assert!(!p.alternative_applies(alt(&p, "linuxthing")));
+    }
+
+    #[test]
+    fn qualifiers_decide_which_architectures_satisfy_a_dependency() {
+        let mut p = Packages::load_fixture("arch/multiarch");
+        let satisfies = |p: &Packages, alt_name: &str, package_name: &str| p.arch_satisfies(alt(p, alt_name), p.get_package_num(package_name).unwrap());
+        // an i386 package is no good until i386 is enabled
+        assert!(!satisfies(&p, "libfoo", "libfoo"));
+        assert!(!satisfies(&p, "helper", "helper"));
+        p.set_arch("amd64", &["i386"]);
+        assert!(satisfies(&p, "libfoo", "libfoo"));
+        // pkg:any needs Multi-Arch: allowed or foreign
+        assert!(satisfies(&p, "tool", "tool"));
+        assert!(!satisfies(&p, "notany", "notany"));
+        // pkg:native needs a native package, even a Multi-Arch: foreign one won't do
+        assert!(satisfies(&p, "plugin", "plugin"));
+        assert!(!satisfies(&p, "plugin", "helper"));
+        // an unqualified dependency takes a foreign package if it's Multi-Arch: foreign
+        assert!(satisfies(&p, "helper", "helper"));
+        assert!(!satisfies(&p, "helper", "libfoo"));
+    }
+
+    #[test]
+    fn rejects_unknown_multi_arch_values() {
+        for ma in [MultiArch::No, MultiArch::Same, MultiArch::Foreign, MultiArch::Allowed] {
+            assert!(ma.to_string().parse::<MultiArch>().ok() == Some(ma));
+        }
+        assert!("sam".parse::<MultiArch>().is_err());
+    }
+}
@@ -7,6 +7,9 @@ use std::time::Duration;
 use std::str;
 use std::sync::atomic::{AtomicI32, Ordering};
 
+use rpkg::debversion::DebianVersionNum;
+use rpkg::error::RpkgError;
+
 use crate::Packages;
 
 struct Collector(Box<String>);
@@ -26,9 +29,34 @@ impl Drop for Packages {
 
 static EASYKEY_COUNTER: AtomicI32 = AtomicI32::new(0);
 
+/// Which checksum a request asks the server for.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Checksum {
+    Md5,
+    Sha256
+}
+
+impl Checksum {
+    /// The part of the URL, after /rest/v1/checksums/, which selects the checksum.
+    fn url_prefix(&self) -> &'static str {
+        match self {
+            Checksum::Md5 => "",
+            Checksum::Sha256 => "sha256/"
+        }
+    }
+
+    /// How to say which checksum was verified; MD5 is the default, so it isn't mentioned.
+    fn label(&self) -> &'static str {
+        match self {
+            Checksum::Md5 => "",
+            Checksum::Sha256 => " (sha256)"
+        }
+    }
+}
+
 pub struct AsyncState {
     server : String,
-    key_pkg : HashMap<i32, (String, i32, String)>,
+    key_pkg : HashMap<i32, (String, i32, String, Checksum)>,
     url_key : HashMap<String, i32>
 }
 
@@ -48,37 +76,89 @@ impl Packages {
     }
 
     /// Retrieves the version number of pkg and calls enq_verify_with_version with that version number.
-    pub fn enq_verify(&mut self, pkg:&str) {
+    pub fn enq_verify(&mut self, pkg:&str) -> Result<(), RpkgError> {
         let version = self.get_available_debver(pkg);
         match version {
-            None => { println!("Error: package {} not defined.", pkg); return },
+            None => { println!("Error: package {} not defined.", pkg); Ok(()) },
             Some(v) => { 
                 let vs = &v.to_string();
-                self.enq_verify_with_version(pkg, vs); 
+                self.enq_verify_with_version(pkg, vs)
             }
+        }
+    }
+
+    /// Enqueues requests for each of args, which is either pkg (for the available version) or pkg=version; or, for
+    /// compatibility, the two arguments pkg version. Checks every package first, so that nothing is enqueued if one of
+    /// them is unknown.
+    pub fn enq_verify_all(&mut self, args: &[&str]) -> Result<(), RpkgError> {
+        for (pkg, version) in self.verify_requests(args)? {
+            match version {
+                None => self.enq_verify(pkg)?,
+                Some(version) => self.enq_verify_with_version(pkg, version)?
+            }
+        }
+        Ok(())
+    }
+
+    /// Turns the arguments of enq-verify into (package, version) requests, where no version means the available one.
+    /// Two arguments are the legacy pkg version form if the second one is a version without even a policy warning
+    /// (so not a word like a package name) and not a known package. Fails on the first unknown package.
+    fn verify_requests<'a>(&self, args: &[&'a str]) -> Result<Vec<(&'a str, Option<&'a str>)>, RpkgError> {
+        let is_version = |arg: &str| DebianVersionNum::validate(arg).is_ok() && DebianVersionNum::warning(arg).is_none();
+        let requests : Vec<(&str, Option<&str>)> = match args {
+            [pkg, version] if is_version(version) && !self.package_name_to_num.contains_key(*version) => vec![(pkg, Some(version))],
+            _ => args.iter().map(|arg| match arg.split_once('=') {
+                Some((pkg, version)) => (pkg, Some(version)),
+                None => (*arg, None)
+            }).collect()
         };
+        for (pkg, _) in &requests {
+            self.get_package_num(pkg)?;
+        }
+        Ok(requests)
     }
 
     /// Enqueues a request for the provided version/package information. Stores any needed state to async_state so that execute() can handle the results and print out needed output.
-    pub fn enq_verify_with_version(&mut self, pkg:&str, version:&str) {
+    /// If we know the SHA256 of that version, also enqueues a request for the server's SHA256.
+    pub fn enq_verify_with_version(&mut self, pkg:&str, version:&str) -> Result<(), RpkgError> {
+        let pkg_num = self.get_package_num(pkg)?;
+        self.enq_checksum_request(pkg, pkg_num, version, Checksum::Md5);
+        if self.local_checksum(pkg_num, version, Checksum::Sha256).is_some() {
+            self.enq_checksum_request(pkg, pkg_num, version, Checksum::Sha256);
+        }
+        Ok(())
+    }
+
+    fn enq_checksum_request(&mut self, pkg:&str, pkg_num: i32, version:&str, checksum: Checksum) {
         let encoded_version = encode(version);
-        let url = format!("http://{}/rest/v1/checksums/{}/{}", self.async_state.server, pkg, encoded_version);
+        let url = format!("http://{}/rest/v1/checksums/{}{}/{}", self.async_state.server, checksum.url_prefix(), pkg, encoded_version);
         let easykey = EASYKEY_COUNTER.fetch_add(1, Ordering::SeqCst);
-        let pkg_num = self.get_package_num(pkg);
         println!("queueing request {}", url);
-        self.async_state.key_pkg.insert(easykey, (String::from(pkg), *pkg_num, version.to_string()));
+        self.async_state.key_pkg.insert(easykey, (String::from(pkg), pkg_num, version.to_string(), checksum));
         self.async_state.url_key.insert(url, easykey);
     }
 
+    /// Returns our checksum of pkg_num at version, if version is one of its candidates and we know that checksum of it.
+    /// Versions are compared as versions, so 1.0 finds the candidate 1.00.
+    fn local_checksum(&self, pkg_num: i32, version: &str, checksum: Checksum) -> Option<&String> {
+        let version = DebianVersionNum::parse_lenient(version).ok()?;
+        let candidate = self.candidates.get(&pkg_num).into_iter().flatten().find(|c| c.version == version)?;
+        match checksum {
+            Checksum::Md5 => candidate.md5sum.as_ref(),
+            Checksum::Sha256 => candidate.sha256.as_ref()
+        }
+    }
+
 
-    /// Asks curl to perform all enqueued requests. For requests that succeed with response code 200, compares received MD5sum with local MD5sum (perhaps stored earlier). For requests that fail with 400+, prints error message.
+    /// Asks curl to perform all enqueued requests. For requests that succeed with response code 200, compares received MD5sum (or SHA256) with local MD5sum (or SHA256, perhaps stored earlier) of the version requested. For requests that fail with 400+, prints error message.
     pub fn execute(&mut self) {
         let mut multi = Multi::new();
 
         let mut easys :Vec<(Easy2Handle<Collector>, i32)> = Vec::new();
         multi.pipelining(true, true).unwrap();
-        for (url, easykey) in self.async_state.url_key.iter() {
-            easys.push((init(&multi, url).unwrap(), *easykey));
+        // each request is only performed once
+        for (url, easykey) in self.async_state.url_key.drain() {
+            easys.push((init(&multi, &url).unwrap(), easykey));
         }
         while multi.perform().unwrap() > 0 {
             multi.wait(&mut [], Duration::from_secs(10)).unwrap();
@@ -89,20 +169,16 @@ impl Packages {
             let easykey = easyhandler.1;
             let resp_code = handler_after.response_code().unwrap();
 
-            let (pkg, pkg_num, version) = self.async_state.key_pkg.get(&easykey).unwrap();
-            let data = handler_after.get_ref().0.clone();
+            let (pkg, pkg_num, version, checksum) = self.async_state.key_pkg.get(&easykey).unwrap();
             if resp_code == 200 {
                 let data = handler_after.get_ref().0.clone();
-                let local_md5 = self.md5sums.get(pkg_num).unwrap();
-                if data.trim() == local_md5 {
-                    println!("verifying {}, matches: {:?}", pkg, true);
-                }
-                else {
-                    println!("verifying {}, matches: {:?}", pkg, true);
-                }
+                let local = self.local_checksum(*pkg_num, version, *checksum);
+                // hex digests, so case doesn't matter
+                let matches = local.is_some_and(|l| l.eq_ignore_ascii_case(data.trim()));
+                println!("verifying {}{}, matches: {:?}", pkg, checksum.label(), matches);
             }
             else {
-                println!("got error {} on request for package {} version {}", resp_code, pkg, version);
+                println!("got error {} on request for package {} version {}{}", resp_code, pkg, version, checksum.label());
             }
             self.async_state.key_pkg.remove(&easykey);
         }
@@ -117,3 +193,31 @@ fn init(multi: &Multi, url:&str) -> Result<Easy2Handle<Collector>, curl::Error>
     easy.verbose(false)?;
     Ok(multi.add2(easy).unwrap())
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::Packages;
+    use super::Checksum;
+
+    #[test]
+    fn tells_the_forms_of_verify_arguments_apart() {
+        let p = Packages::load_fixture("verify/checksums");
+        assert_eq!(p.verify_requests(&["a", "1.0"]).unwrap(), vec![("a", Some("1.0"))]);
+        assert_eq!(p.verify_requests(&["a", "b"]).unwrap(), vec![("a", None), ("b", None)]);
+        assert_eq!(p.verify_requests(&["a=1.0", "b", "c"]).unwrap(), vec![("a", Some("1.0")), ("b", None), ("c", None)]);
+        // a word is an unknown package, not a version
+        assert_eq!(p.verify_requests(&["a", "nosuch"]).err().map(|e| e.to_string()), Some(String::from("no such package nosuch")));
+        assert!(p.verify_requests(&["a", "b=2.0", "nosuch=1.0"]).is_err());
+    }
+
+    #[test]
+    fn finds_the_local_checksum_of_an_equal_version() {
+        let p = Packages::load_fixture("verify/checksums");
+        let a = p.get_package_num("a").unwrap();
+        assert_eq!(p.local_checksum(a, "1.0", Checksum::Md5).map(String::as_str), Some("aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa"));
+        assert!(p.local_checksum(a, "1.00", Checksum::Sha256).is_some());
+        assert!(p.local_checksum(a, "1.0.1", Checksum::Md5).is_none());
+        let b = p.get_package_num("b").unwrap();
+        assert!(p.local_checksum(b, "2.0", Checksum::Sha256).is_none());
+    }
+}
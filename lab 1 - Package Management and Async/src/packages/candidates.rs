@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+
+use rpkg::debversion::DebianVersionNum;
+
+use crate::Packages;
+use crate::packages::{Candidate, Dependency, DependencyKind, ProvidedPackage};
+use crate::packages::preferences::DEFAULT_PRIORITY;
+use crate::packages::record::PackageRecord;
+
+impl Candidate {
+    pub fn new(version: DebianVersionNum, source: usize) -> Candidate {
+        Candidate { version, source, priority : DEFAULT_PRIORITY, md5sum : None, sha256 : None, architecture : None, multi_arch : None,
+                    dependencies : None, pre_dependencies : None, recommends : None, suggests : None,
+                    conflicts : None, breaks : None, replaces : None, provides : None, record : PackageRecord::new() }
+    }
+
+    fn dependencies_of_kind(&self, kind: DependencyKind) -> Option<&Vec<Dependency>> {
+        match kind {
+            DependencyKind::PreDepends => self.pre_dependencies.as_ref(),
+            DependencyKind::Depends => self.dependencies.as_ref(),
+            DependencyKind::Recommends => self.recommends.as_ref(),
+            DependencyKind::Suggests => self.suggests.as_ref()
+        }
+    }
+}
+
+impl Packages {
+    /// Returns the index of filename in sources, adding it at the end if it's new.
+    pub fn source_index(&mut self, filename: &str) -> usize {
+        match self.sources.iter().position(|s| s == filename) {
+            Some(i) => i,
+            None => {
+                self.sources.push(filename.to_string());
+                self.sources.len() - 1
+            }
+        }
+    }
+
+    /// Forgets the candidates loaded from source, before it is loaded again.
+    pub fn forget_source(&mut self, source: usize) {
+        for candidates in self.candidates.values_mut() {
+            candidates.retain(|c| c.source != source);
+        }
+    }
+
+    /// Adds candidate as a version of package_num. A version which an earlier source already has is kept as it was,
+    /// unless it came from the same source, i.e. the same version appears twice in one file; then the later one wins.
+    pub fn add_candidate(&mut self, package_num: i32, candidate: Candidate) {
+        let candidates = self.candidates.entry(package_num).or_default();
+        match candidates.iter().position(|c| c.version == candidate.version) {
+            Some(i) if candidates[i].source == candidate.source => candidates[i] = candidate,
+            Some(_) => (),
+            None => candidates.push(candidate)
+        }
+    }
+
+    /// Adds a version known only from a CSV file of MD5 sums (and maybe SHA256s); if it's already a candidate, just
+    /// sets its checksums.
+    pub fn add_csv_candidate(&mut self, package_num: i32, version: DebianVersionNum, md5sum: String, sha256: Option<String>, source: usize) {
+        let candidates = self.candidates.entry(package_num).or_default();
+        let candidate = match candidates.iter().position(|c| c.version == version) {
+            Some(i) => &mut candidates[i],
+            None => {
+                candidates.push(Candidate::new(version, source));
+                candidates.last_mut().unwrap()
+            }
+        };
+        candidate.md5sum = Some(md5sum);
+        if sha256.is_some() {
+            candidate.sha256 = sha256;
+        }
+    }
+
+    /// Gives each package's candidates their pin priorities (see pin_priority) and sorts them into order of
+    /// preference: highest priority first, then those from the file loaded first, then newest first. The preferred
+    /// candidate is the available version, unless its priority is negative, which means it is never installed: the
+    /// per-package maps (available_debvers, dependencies, md5sums, sha256sums, provides, ...) are rebuilt to hold its
+    /// data. Called whenever a file of candidates or of preferences has been loaded.
+    pub fn choose_candidates(&mut self) {
+        self.candidates.retain(|_, candidates| !candidates.is_empty());
+        let mut all_candidates = std::mem::take(&mut self.candidates);
+        for (package_num, candidates) in all_candidates.iter_mut() {
+            for c in candidates.iter_mut() {
+                c.priority = self.pin_priority(*package_num, c);
+            }
+            candidates.sort_by(|a, b| b.priority.cmp(&a.priority).then(a.source.cmp(&b.source)).then(b.version.cmp(&a.version)));
+        }
+        self.candidates = all_candidates;
+
+        self.available_debvers.clear();
+        self.md5sums.clear();
+        self.sha256sums.clear();
+        self.multi_arch.clear();
+        self.pre_dependencies.clear();
+        self.recommends.clear();
+        self.suggests.clear();
+        self.conflicts.clear();
+        self.breaks.clear();
+        self.replaces.clear();
+        self.provides.clear();
+        self.records.clear();
+        // every package has an entry in dependencies, even if it has no candidates
+        for deps in self.dependencies.values_mut() {
+            deps.clear();
+        }
+        let mut providers : HashMap<i32, Vec<ProvidedPackage>> = HashMap::new();
+        // in package number order, so that each virtual package's providers come in the order they were first seen
+        let mut package_nums : Vec<i32> = self.candidates.keys().copied().collect();
+        package_nums.sort_unstable();
+        for package_num in package_nums {
+            let c = match self.candidates[&package_num].first().filter(|c| c.priority >= 0) {
+                None => continue,
+                Some(c) => c
+            };
+            self.available_debvers.insert(package_num, c.version.clone());
+            self.dependencies.insert(package_num, c.dependencies.clone().unwrap_or_default());
+            self.records.insert(package_num, c.record.clone());
+            if let Some(md5sum) = &c.md5sum { self.md5sums.insert(package_num, md5sum.clone()); }
+            if let Some(sha256) = &c.sha256 { self.sha256sums.insert(package_num, sha256.clone()); }
+            // an installed package's Architecture stays if the available one doesn't say
+            if let Some(arch) = &c.architecture { self.architectures.insert(package_num, arch.clone()); }
+            if let Some(ma) = c.multi_arch { self.multi_arch.insert(package_num, ma); }
+            if let Some(ds) = &c.pre_dependencies { self.pre_dependencies.insert(package_num, ds.clone()); }
+            if let Some(ds) = &c.recommends { self.recommends.insert(package_num, ds.clone()); }
+            if let Some(ds) = &c.suggests { self.suggests.insert(package_num, ds.clone()); }
+            if let Some(rels) = &c.conflicts { self.conflicts.insert(package_num, rels.clone()); }
+            if let Some(rels) = &c.breaks { self.breaks.insert(package_num, rels.clone()); }
+            if let Some(rels) = &c.replaces { self.replaces.insert(package_num, rels.clone()); }
+            if let Some(provided) = &c.provides {
+                for p in provided {
+                    providers.entry(p.package_num).or_default()
+                        .push(ProvidedPackage { package_num, version : p.version.clone() });
+                }
+                self.provides.insert(package_num, provided.clone());
+            }
+        }
+        self.providers = providers;
+    }
+
+    /// Returns the candidate which describes package_num at version, or, if none does (say, an installed version
+    /// which is no longer available), the preferred candidate.
+    pub fn candidate_for(&self, package_num: i32, version: &DebianVersionNum) -> Option<&Candidate> {
+        let candidates = self.candidates.get(&package_num)?;
+        candidates.iter().find(|c| c.version == *version).or_else(|| candidates.first())
+    }
+
+    /// Like dependencies_of_kind, but for package_num at version (as candidate_for picks it) rather than at its
+    /// available version.
+    pub fn dependencies_of_version(&self, kind: DependencyKind, package_num: i32, version: Option<&DebianVersionNum>) -> Option<&Vec<Dependency>> {
+        match version.and_then(|v| self.candidate_for(package_num, v)) {
+            Some(c) => c.dependencies_of_kind(kind),
+            None => self.dependencies_of_kind(kind, package_num)
+        }
+    }
+
+    /// Returns the versions of package_num which may be installed (those with a priority of at least 0), preferred first.
+    pub fn candidate_versions(&self, package_num: i32) -> Vec<&DebianVersionNum> {
+        self.candidates.get(&package_num).into_iter().flatten().filter(|c| c.priority >= 0).map(|c| &c.version).collect()
+    }
+
+    /// Returns the pin priority of package_num's preferred candidate, if it has one that may be installed.
+    pub fn preferred_priority(&self, package_num: i32) -> Option<i32> {
+        self.candidates.get(&package_num).and_then(|c| c.first()).map(|c| c.priority).filter(|p| *p >= 0)
+    }
+
+    /// Describes the candidates of package_num, preferred first, as "version (source)" (see source2str).
+    pub fn candidates2str(&self, package_num: i32) -> String {
+        self.candidates.get(&package_num).into_iter().flatten()
+            .map(|c| format!("{} ({})", c.version, self.source2str(c.source))).collect::<Vec<_>>().join(", ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Packages;
+    use crate::packages::fixture;
+
+    /// The candidates of package_name: version, source file name (without its directory) and MD5 sum.
+    fn candidates(p: &Packages, package_name: &str) -> Vec<(String, String, Option<String>)> {
+        p.candidates[&p.get_package_num(package_name).unwrap()].iter()
+            .map(|c| (c.version.to_string(), p.sources[c.source].rsplit('/').next().unwrap().to_string(), c.md5sum.clone())).collect()
+    }
+
+    fn candidate(version: &str, source: &str, md5sum: &str) -> (String, String, Option<String>) {
+        (version.to_string(), source.to_string(), Some(md5sum.to_string()))
+    }
+
+    #[test]
+    fn keeps_the_first_source_of_a_version() {
+        let mut p = Packages::load_fixture("candidates/stable");
+        p.parse_packages(&fixture("candidates/backports.Packages")).unwrap();
+        // both files have the same priority, so stable's 1.0 is preferred to backports' 2.0, and backports' 1.0 is dropped
+        assert_eq!(candidates(&p, "b"), vec![candidate("1.0", "stable.Packages", "11111111111111111111111111111111"),
+                                             candidate("2.0", "backports.Packages", "33333333333333333333333333333333")]);
+        assert_eq!(p.get_md5sum("b"), Some("11111111111111111111111111111111"));
+    }
+
+    #[test]
+    fn reloading_a_source_replaces_its_candidates() {
+        let mut p = Packages::load_fixture("candidates/stable");
+        p.parse_packages(&fixture("candidates/backports.Packages")).unwrap();
+        let before = candidates(&p, "b");
+        p.parse_packages(&fixture("candidates/stable.Packages")).unwrap();
+        p.parse_packages(&fixture("candidates/backports.Packages")).unwrap();
+        assert_eq!(candidates(&p, "b"), before);
+        assert_eq!(p.sources.len(), 2);
+    }
+
+    #[test]
+    fn a_csv_sets_the_checksum_of_a_candidate_from_another_source() {
+        let mut p = Packages::load_fixture("candidates/stable");
+        p.parse_csv(&fixture("candidates/md5s.csv")).unwrap();
+        assert_eq!(candidates(&p, "b"), vec![candidate("1.0", "stable.Packages", "44444444444444444444444444444444")]);
+        assert_eq!(p.get_md5sum("b"), Some("44444444444444444444444444444444"));
+    }
+
+    #[test]
+    fn falls_back_to_a_candidate_which_is_not_preferred() {
+        let p = Packages::load_fixture("candidates/fallback");
+        let b = p.get_package_num("b").unwrap();
+        assert_eq!(p.get_available_debver("b").map(|v| v.to_string()), Some(String::from("2.0")));
+        let plan = p.compute_how_to_install(&[p.get_package_num("k").unwrap()], false).unwrap();
+        assert_eq!(p.planned_version(&plan, b).map(|v| v.to_string()), Some(String::from("1.5")));
+    }
+}
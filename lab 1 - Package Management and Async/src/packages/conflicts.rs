@@ -0,0 +1,59 @@
+use rpkg::debversion::DebianVersionNum;
+use rpkg::error::RpkgError;
+
+use crate::Packages;
+
+impl Packages {
+    /// Returns true if package_num declares that it Replaces target (installed at target_version).
+    pub fn package_replaces(&self, package_num: i32, target: i32, target_version: &DebianVersionNum) -> bool {
+        match self.replaces.get(&package_num) {
+            None => false,
+            Some(rels) => rels.iter().any(|rel| rel.package_num == target && self.version_satisfies(rel, target_version))
+        }
+    }
+
+    /// Prints the Conflicts, Breaks and Replaces relations of package_name, followed by the installed packages
+    /// which it can't be installed alongside (in either direction).
+    pub fn print_conflicts(&self, package_name: &str) -> Result<(), RpkgError> {
+        let package_num = self.get_package_num(package_name)?;
+        println!("Package {}:", package_name);
+        for (field, rels) in [("Conflicts", self.conflicts.get(&package_num)), ("Breaks", self.breaks.get(&package_num)), ("Replaces", self.replaces.get(&package_num))] {
+            if let Some(rels) = rels {
+                println!("{}: {}", field, self.rels2str(rels));
+            }
+        }
+
+        let version = self.available_debvers.get(&package_num);
+        for (kind, relations) in [("conflicts with", &self.conflicts), ("breaks", &self.breaks)] {
+            // package_name's own relations against installed packages
+            for rel in relations.get(&package_num).into_iter().flatten() {
+                if rel.package_num == package_num {
+                    continue
+                }
+                if let Some(iv) = self.installed_debvers.get(&rel.package_num) {
+                    if self.version_satisfies(rel, iv) {
+                        let target = self.get_package_name(rel.package_num);
+                        let replaced = if kind == "conflicts with" && self.package_replaces(package_num, rel.package_num, iv) { " (replaced)" } else { "" };
+                        println!("- {} {} installed {} {}{}", package_name, kind, target, iv, replaced);
+                    }
+                }
+            }
+            // installed packages' relations against package_name
+            let version = match version {
+                None => continue,
+                Some(v) => v
+            };
+            for (other, rels) in relations {
+                if *other == package_num || !self.installed_debvers.contains_key(other) {
+                    continue
+                }
+                for rel in rels {
+                    if rel.package_num == package_num && self.version_satisfies(rel, version) {
+                        println!("- installed {} {} {} {}", self.get_package_name(*other), kind, package_name, version);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,22 +1,31 @@
-use rpkg::debversion;
+use rpkg::error::RpkgError;
 use crate::Packages;
 use crate::packages::Dependency;
 
 impl Packages {
-    /// Gets the dependencies of package_name, and prints out whether they are satisfied (and by which library/version) or not.
-    pub fn deps_available(&self, package_name: &str) {
-        if !self.package_exists(package_name) {
-            println!("no such package {}", package_name);
-            return;
+    /// Calls deps_available on each of package_names, after checking that they're all known, so that nothing is
+    /// printed if one of them isn't.
+    pub fn deps_available_all(&self, package_names: &[&str]) -> Result<(), RpkgError> {
+        for package_name in package_names {
+            self.get_package_num(package_name)?;
         }
+        package_names.iter().try_for_each(|package_name| self.deps_available(package_name))
+    }
+
+    /// Gets the dependencies of package_name, and prints out whether they are satisfied (and by which library/version) or not.
+    pub fn deps_available(&self, package_name: &str) -> Result<(), RpkgError> {
+        let package_num = self.get_package_num(package_name)?;
         println!("Package {}:", package_name);
-        let package_num = self.get_package_num(package_name);
-        let deps = self.dependencies.get(package_num);
+        let deps = self.dependencies.get(&package_num);
         match deps {
             None => (),
             Some(dep) => {
                 for i in dep {
-                    //println!("- dependency {:?}", dep);
+                    println!("- dependency {:?}", self.dep2str(i));
+                    if !self.dependency_applies(i) {
+                        println!("-> not applicable on {}", self.native_arch);
+                        continue
+                    }
                     let res = self.dep_is_satisfied(i);
                     match res {
                         None => {
@@ -32,76 +41,66 @@ impl Packages {
                 }
             }
         }
+        Ok(())
     }
 
-        //println!("- dependency {:?}", "dep");
-        //println!("+ {} satisfied by installed version {}", "dep", "459");
-        // some sort of for loop...
-
     /// Returns Some(package) which satisfies dependency dd, or None if not satisfied.
+    /// The package may be one which provides an alternative, if the alternative is a virtual package.
+    /// Only packages whose architecture fits the alternative's architecture qualifier count.
     pub fn dep_is_satisfied(&self, dd:&Dependency) -> Option<&str> {
         for dep in dd {
             let package_num = dep.package_num;
             let package_name = self.get_package_name(package_num);
-            if let Some(iv) = self.installed_debvers.get(&package_num) {
-                if let Some(vers) = &dep.rel_version {
-                    let op = &vers.0;
-                    let v = &vers.1.parse::<debversion::DebianVersionNum>().unwrap();
-                    println!("- dependency {} \"({} {})\"", package_name, op.to_string(), &vers.1);
-                    let is_satisfied = debversion::cmp_debversion_with_op(op, iv, v);
-                    if is_satisfied {
-                        return Some(package_name)
-                    }
-
-                }
-                else {
-                    if let Some(vers) = &dep.rel_version {
-                        let op = &vers.0.to_string();
-                        let v = &vers.1;
-                        println!("- dependency {} \"({} {})\"", package_name, op, v) ;
-                    }
-                    else {
-                        println!("- dependency {} ", package_name);
-                    }
+            if !self.alternative_applies(dep) {
+                continue
+            }
+            if let Some(iv) = self.installed_debvers.get(&package_num).filter(|_| self.arch_satisfies(dep, package_num)) {
+                if self.version_satisfies(dep, iv) {
                     return Some(package_name)
                 }
-
-            }
-            if let Some(vers) = &dep.rel_version {
-                let op = &vers.0.to_string();
-                let v = &vers.1;
-                println!("- dependency {} \"({} {})\"", package_name, op, v) ;
             }
-            else {
-                println!("- dependency {} ", package_name);
+            if let Some(provider) = self.providers_satisfying(dep).into_iter().find(|p| self.installed_debvers.contains_key(p) && self.arch_satisfies(dep, *p)) {
+                return Some(self.get_package_name(provider))
             }
         }
-        return None;
+        None
     }
 
     /// Returns a Vec of packages which would satisfy dependency dd but for the version.
-    /// Used by the how-to-install command, which calls compute_how_to_install().
+    /// This includes installed packages which provide an alternative, but unversioned or at the wrong version.
+    /// Used by the how-to-install command to explain why compute_how_to_install() found no solution.
     pub fn dep_satisfied_by_wrong_version(&self, dd:&Dependency) -> Vec<&str> {
         assert! (self.dep_is_satisfied(dd).is_none());
         let mut result = vec![];
         for dep in dd {
             let package_num = dep.package_num;
             let package_name = self.get_package_name(package_num);
-            if let Some(iv) = self.installed_debvers.get(&package_num) {
-                if let Some(vers) = &dep.rel_version {
-                    let op = &vers.0;
-                    let v = &vers.1.parse::<debversion::DebianVersionNum>().unwrap();
-                    let is_satisfied = debversion::cmp_debversion_with_op(op, iv, v);
-                    if !is_satisfied {
-                        result.push(package_name);
-                    }
+            if let Some(iv) = self.installed_debvers.get(&package_num).filter(|_| self.arch_satisfies(dep, package_num)) {
+                if !self.version_satisfies(dep, iv) {
+                    result.push(package_name);
                 }
             }
-            else {
-                continue
+            for provider in self.providers_not_satisfying(dep) {
+                if self.installed_debvers.contains_key(&provider) && self.arch_satisfies(dep, provider) {
+                    result.push(self.get_package_name(provider));
+                }
             }
         }
         return result;
     }
 
 }
+
+#[cfg(test)]
+mod tests {
+    use rpkg::error::RpkgError;
+
+    use crate::Packages;
+
+    #[test]
+    fn checks_every_package_name_first() {
+        let p = Packages::load_fixture("verify/checksums");
+        assert!(p.deps_available_all(&["a", "b"]).is_ok());
+        assert!(matches!(p.deps_available_all(&["a", "nosuch", "b"]), Err(RpkgError::UnknownPackage(name)) if name == "nosuch"));
+    }
+}
@@ -0,0 +1,158 @@
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use petgraph::algo::tarjan_scc;
+use petgraph::graphmap::DiGraphMap;
+
+use rpkg::debversion::DebianVersionNum;
+use rpkg::error::RpkgError;
+
+use crate::Packages;
+use crate::packages::{DependencyKind, RelVersionedPackageNum};
+
+impl Packages {
+    /// Builds the graph of hard dependencies among packages: there is an edge from p to q if q, at the version
+    /// version_of gives it, satisfies one of the Pre-Depends or Depends of p at its version (as an alternative, or by
+    /// providing one).
+    /// Nodes are added in the order of packages.
+    pub fn dependency_graph<'a>(&'a self, packages: &[i32], version_of: &dyn Fn(i32) -> Option<&'a DebianVersionNum>) -> DiGraphMap<i32, ()> {
+        let mut graph = DiGraphMap::new();
+        for package_num in packages {
+            graph.add_node(*package_num);
+        }
+        for package_num in packages {
+            for kind in [DependencyKind::PreDepends, DependencyKind::Depends].iter() {
+                for dep in self.dependencies_of_version(*kind, *package_num, version_of(*package_num)).into_iter().flatten().filter(|dep| self.dependency_applies(dep)) {
+                    for alt in dep.iter().filter(|alt| self.alternative_applies(alt)) {
+                        let itself = Some(alt.package_num)
+                            .filter(|q| self.arch_satisfies(alt, *q) && version_of(*q).is_some_and(|v| self.version_satisfies(alt, v)));
+                        let providers = self.providers_satisfying(alt).into_iter().filter(|q| self.arch_satisfies(alt, *q) && version_of(*q).is_some());
+                        for q in itself.into_iter().chain(providers) {
+                            if graph.contains_node(q) {
+                                graph.add_edge(*package_num, q, ());
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        graph
+    }
+
+    /// Sorts packages so that each one comes after the packages it (pre-)depends on, as the dependency graph says.
+    /// Packages on a dependency cycle can't be ordered like that: they come together, in their original order, and are
+    /// also returned as a cycle. Otherwise packages keep their original order as far as possible.
+    pub fn install_order<'a>(&'a self, packages: &[i32], version_of: &dyn Fn(i32) -> Option<&'a DebianVersionNum>) -> (Vec<i32>, Vec<Vec<i32>>) {
+        let graph = self.dependency_graph(packages, version_of);
+        let position = |pn: &i32| packages.iter().position(|p| p == pn);
+        let mut order = vec![];
+        let mut cycles = vec![];
+        // Tarjan's algorithm finds the strongly connected components in reverse topological order, i.e. dependencies first
+        for mut component in tarjan_scc(&graph) {
+            component.sort_by_key(position);
+            if component.len() > 1 || graph.contains_edge(component[0], component[0]) {
+                cycles.push(component.clone());
+            }
+            order.extend(component);
+        }
+        (order, cycles)
+    }
+
+    /// Returns package_num and the packages it needs, nearest first: like transitive_dep_solution, this follows the
+    /// first alternative of each dependency, but of Pre-Depends as well as Depends.
+    fn dependency_closure(&self, package_num: i32) -> Vec<i32> {
+        let mut closure = vec![package_num];
+        let mut visited = HashSet::from([package_num]);
+        let mut next = 0;
+        while let Some(pn) = closure.get(next).copied() {
+            next += 1;
+            for kind in [DependencyKind::PreDepends, DependencyKind::Depends].iter() {
+                for dep in self.dependencies_of_kind(*kind, pn).into_iter().flatten() {
+                    if visited.insert(dep[0].package_num) {
+                        closure.push(dep[0].package_num);
+                    }
+                }
+            }
+        }
+        closure
+    }
+
+    /// Writes the dependency closure of package_name, as dependency_closure finds it, to fname in Graphviz DOT
+    /// format. Each Pre-Depends (bold) and Depends of a package in the closure becomes an edge, labelled with its version
+    /// constraint if any; a dependency with alternatives goes through an "or" node instead. Installed packages are
+    /// filled green, the others white, and packages outside the closure (alternatives not taken) are dashed.
+    pub fn write_dependency_graph(&self, package_name: &str, fname: &str) -> Result<(), RpkgError> {
+        let package_num = self.get_package_num(package_name)?;
+        let closure = self.dependency_closure(package_num);
+
+        let mut nodes = closure.clone();
+        let mut edges = String::new();
+        let mut or_nodes = String::new();
+        for pn in &closure {
+            for kind in [DependencyKind::PreDepends, DependencyKind::Depends].iter() {
+                let bold = *kind == DependencyKind::PreDepends;
+                for (i, dep) in self.dependencies_of_kind(*kind, *pn).into_iter().flatten().enumerate() {
+                    let from = if dep.len() == 1 {
+                        format!("{:?}", self.get_package_name(*pn))
+                    } else {
+                        let or_node = format!("{:?}", format!("{} {} {}", self.get_package_name(*pn), kind.field_name(), i));
+                        writeln!(or_nodes, "    {} [label=\"or\", shape=diamond];", or_node).unwrap();
+                        writeln!(edges, "    {:?} -> {}{};", self.get_package_name(*pn), or_node, if bold { " [style=bold]" } else { "" }).unwrap();
+                        or_node
+                    };
+                    for alt in dep {
+                        if !nodes.contains(&alt.package_num) {
+                            nodes.push(alt.package_num);
+                        }
+                        writeln!(edges, "    {} -> {:?} [label={:?}{}];", from, self.get_package_name(alt.package_num), self.edge_label(alt), if bold { ", style=bold" } else { "" }).unwrap();
+                    }
+                }
+            }
+        }
+
+        let mut out = format!("digraph {:?} {{\n    node [shape=box, style=filled];\n", package_name);
+        for pn in &nodes {
+            let fill = if self.installed_debvers.contains_key(pn) { "palegreen" } else { "white" };
+            let border = if closure.contains(pn) { "" } else { ", style=\"filled,dashed\"" };
+            writeln!(out, "    {:?} [fillcolor={}{}];", self.get_package_name(*pn), fill, border).unwrap();
+        }
+        out.push_str(&or_nodes);
+        out.push_str(&edges);
+        out.push_str("}\n");
+        std::fs::write(fname, out).map_err(|e| RpkgError::Io { file : fname.to_string(), source : e })?;
+        println!("Graph saved: {} packages", nodes.len());
+        Ok(())
+    }
+
+    /// The label of a dependency edge: the architecture qualifier and version constraint, if any.
+    fn edge_label(&self, alt: &RelVersionedPackageNum) -> String {
+        let arch = alt.arch_qualifier.iter().map(|q| format!(":{}", q));
+        let constraint = alt.rel_version.iter().map(|c| c.to_string());
+        arch.chain(constraint).collect::<Vec<_>>().join(" ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Packages;
+
+    #[test]
+    fn closure_follows_pre_depends() {
+        let p = Packages::load_fixture("graph/predepends");
+        let closure = p.dependency_closure(p.get_package_num("a").unwrap());
+        assert_eq!(p.names(&closure), vec!["a", "b", "c", "d", "e"]);
+    }
+
+    #[test]
+    fn install_order_puts_a_cycle_together_after_its_dependencies() {
+        let p = Packages::load_fixture("graph/cycles");
+        let packages : Vec<i32> = ["cycuser", "cyca", "cycb", "app", "libc6"].iter().map(|n| p.get_package_num(n).unwrap()).collect();
+        let (order, cycles) = p.install_order(&packages, &|pn| p.available_debvers.get(&pn));
+        let order : Vec<&str> = order.iter().map(|pn| p.get_package_name(*pn)).collect();
+        let position = |name: &str| order.iter().position(|n| *n == name).unwrap();
+        assert!(position("libc6") < position("cyca"));
+        assert_eq!(position("cycb"), position("cyca") + 1);
+        assert_eq!(order.last(), Some(&"cycuser"));
+        assert_eq!(cycles.iter().map(|c| p.names(c)).collect::<Vec<_>>(), vec![vec!["cyca", "cycb"]]);
+    }
+}
@@ -1,45 +1,172 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
 use std::sync::atomic::{AtomicI32, Ordering};
 
 use itertools::Itertools;
 
-use rpkg::debversion;
-use rpkg::debversion::{DebianVersionNum,VersionRelation};
+use rpkg::debversion::{DebianVersionNum,VersionConstraint};
+use rpkg::error::RpkgError;
 
 mod deps_available;
 mod solvers;
 mod parsers;
 mod async_fns;
+mod conflicts;
+mod providers;
+mod arch;
+mod rdepends;
+mod removal;
+mod upgrade;
+mod transactions;
+mod graph;
+mod why;
+mod search;
+mod record;
+mod candidates;
+mod preferences;
+mod repo;
 
 use crate::packages::async_fns::AsyncState;
+use crate::packages::arch::MultiArch;
+use crate::packages::transactions::Transaction;
+use crate::packages::record::PackageRecord;
+use crate::packages::preferences::Preference;
+use crate::packages::repo::ReleaseTag;
+pub use crate::packages::search::SearchOptions;
 
 static PACKAGE_COUNTER: AtomicI32 = AtomicI32::new(0);
 
 pub struct Packages {
     dependencies : HashMap<i32,Vec<Dependency>>,
+    pre_dependencies : HashMap<i32,Vec<Dependency>>,
+    recommends : HashMap<i32,Vec<Dependency>>,
+    suggests : HashMap<i32,Vec<Dependency>>,
+    reverse_dependencies : HashMap<i32,Vec<(i32,DependencyKind)>>, // package -> packages with a dependency on it, and its kind
+    conflicts : HashMap<i32,Vec<RelVersionedPackageNum>>,
+    breaks : HashMap<i32,Vec<RelVersionedPackageNum>>,
+    replaces : HashMap<i32,Vec<RelVersionedPackageNum>>,
+    provides : HashMap<i32,Vec<ProvidedPackage>>, // provider -> virtual packages it provides
+    providers : HashMap<i32,Vec<ProvidedPackage>>, // virtual package -> packages providing it
     md5sums : HashMap<i32,String>,
+    sha256sums : HashMap<i32,String>,
+    records : HashMap<i32,PackageRecord>, // every field of the package's stanza, as text
+    candidates : HashMap<i32,Vec<Candidate>>, // every available version, preferred first; the maps above hold the first
+    sources : Vec<String>, // the files candidates were loaded from, in the order they were first loaded
+    preferences : Vec<Preference>, // apt pins, which give candidates their priorities
+    releases : HashMap<usize,ReleaseTag>, // source -> the suite and component it belongs to, for sources loaded by load-repo
+    architectures : HashMap<i32,String>,
+    multi_arch : HashMap<i32,MultiArch>,
+    native_arch : String,
+    foreign_archs : Vec<String>,
+    lenient_versions : bool, // accept versions which break policy rules when loading files
     available_debvers : HashMap<i32,DebianVersionNum>,
     installed_debvers : HashMap<i32,DebianVersionNum>,
+    auto_installed : HashSet<i32>, // installed packages marked Auto-Installed, i.e. only there to satisfy dependencies
+    transactions : Vec<Transaction>, // install/remove commands applied so far, for rollback
     package_name_to_num : HashMap<String, i32>,
     package_num_to_name : HashMap<i32, String>,
     async_state : AsyncState,
 }
 
 // Dependency([X, Y, Z]) means X|Y|Z
+#[derive(Clone)]
 pub struct RelVersionedPackageNum {
     package_num : i32,
-    rel_version : Option<(VersionRelation, String)>
+    rel_version : Option<VersionConstraint>, // X (>= 1.2)
+    arch_qualifier : Option<String>, // X:any, X:native, X:amd64
+    arch_restrictions : Vec<(bool, String)> // X [amd64 !i386] is [(false, amd64), (true, i386)]
 }
 pub type Dependency = Vec<RelVersionedPackageNum>;
 
+// one end of a Provides relation: "Provides: foo (= 1.0)" is stored as ProvidedPackage(foo, 1.0) in provides,
+// and the reverse, ProvidedPackage(provider, 1.0), in providers.
+#[derive(Clone)]
+pub struct ProvidedPackage {
+    package_num : i32,
+    version : Option<DebianVersionNum>
+}
+
+/// One available version of a package, as a stanza of a Packages file (or a row of a CSV file) describes it.
+/// A field which the stanza doesn't have is None. See choose_candidates for how one is preferred over the others.
+pub struct Candidate {
+    version : DebianVersionNum,
+    source : usize, // index into sources
+    priority : i32, // pin priority, from preferences
+    md5sum : Option<String>,
+    sha256 : Option<String>,
+    architecture : Option<String>,
+    multi_arch : Option<MultiArch>,
+    dependencies : Option<Vec<Dependency>>,
+    pre_dependencies : Option<Vec<Dependency>>,
+    recommends : Option<Vec<Dependency>>,
+    suggests : Option<Vec<Dependency>>,
+    conflicts : Option<Vec<RelVersionedPackageNum>>,
+    breaks : Option<Vec<RelVersionedPackageNum>>,
+    replaces : Option<Vec<RelVersionedPackageNum>>,
+    provides : Option<Vec<ProvidedPackage>>,
+    record : PackageRecord
+}
+
+/// The control fields that declare dependencies, strongest first.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum DependencyKind {
+    PreDepends,
+    Depends,
+    Recommends,
+    Suggests
+}
+
+impl DependencyKind {
+    pub const ALL : [DependencyKind; 4] = [DependencyKind::PreDepends, DependencyKind::Depends, DependencyKind::Recommends, DependencyKind::Suggests];
+
+    pub fn field_name(&self) -> &'static str {
+        match self {
+            DependencyKind::PreDepends => "Pre-Depends",
+            DependencyKind::Depends => "Depends",
+            DependencyKind::Recommends => "Recommends",
+            DependencyKind::Suggests => "Suggests"
+        }
+    }
+
+    fn verb(&self) -> &'static str {
+        match self {
+            DependencyKind::PreDepends => "pre-depends on",
+            DependencyKind::Depends => "depends on",
+            DependencyKind::Recommends => "recommends",
+            DependencyKind::Suggests => "suggests"
+        }
+    }
+}
+
 impl Packages {
     pub fn new() -> Packages {
         Packages { 
             dependencies : HashMap::new(), 
+            pre_dependencies : HashMap::new(),
+            recommends : HashMap::new(),
+            suggests : HashMap::new(),
+            reverse_dependencies : HashMap::new(),
+            conflicts : HashMap::new(),
+            breaks : HashMap::new(),
+            replaces : HashMap::new(),
+            provides : HashMap::new(),
+            providers : HashMap::new(),
             md5sums : HashMap::new(),
+            sha256sums : HashMap::new(),
+            records : HashMap::new(),
+            candidates : HashMap::new(),
+            sources : vec![],
+            preferences : vec![],
+            releases : HashMap::new(),
+            architectures : HashMap::new(),
+            multi_arch : HashMap::new(),
+            native_arch : String::from(arch::DEFAULT_NATIVE_ARCH),
+            foreign_archs : vec![],
+            lenient_versions : false,
             available_debvers : HashMap::new(),
             installed_debvers : HashMap::new(),
+            auto_installed : HashSet::new(),
+            transactions : vec![],
             package_name_to_num : HashMap::new(), 
             package_num_to_name : HashMap::new(),
             async_state : AsyncState::new(),
@@ -55,9 +182,9 @@ impl Packages {
         return self.package_num_to_name.get(&package_num).unwrap();
     }
 
-    // panics if package_name doesn't already exist
-    fn get_package_num(&self, package_name: &str) -> &i32 {
-        return self.package_name_to_num.get(package_name).unwrap();
+    // fails with UnknownPackage if package_name doesn't already exist
+    fn get_package_num(&self, package_name: &str) -> Result<i32, RpkgError> {
+        self.package_name_to_num.get(package_name).copied().ok_or_else(|| RpkgError::UnknownPackage(package_name.to_string()))
     }
 
     // inserts package_name into package_name_to_num if it doesn't already exist
@@ -74,10 +201,6 @@ impl Packages {
         }
     }
 
-    pub fn package_exists(&self, package_name: &str) -> bool {
-        return self.package_name_to_num.contains_key(package_name);
-    }
-
     // accessor methods for various maps
     pub fn get_available_debver(&self, package_name: &str) -> Option<&DebianVersionNum> {
         let package_num = self.package_name_to_num.get(package_name);
@@ -112,108 +235,292 @@ impl Packages {
         }
     }
 
+    pub fn get_sha256(&self, package_name: &str) -> Option<&str> {
+        self.package_name_to_num.get(package_name).and_then(|pn| self.sha256sums.get(pn)).map(|s| s.as_str())
+    }
+
+    /// Returns package_num's dependencies of the given kind; Depends are kept in the dependencies map.
+    fn dependencies_of_kind(&self, kind: DependencyKind, package_num: i32) -> Option<&Vec<Dependency>> {
+        match kind {
+            DependencyKind::PreDepends => self.pre_dependencies.get(&package_num),
+            DependencyKind::Depends => self.dependencies.get(&package_num),
+            DependencyKind::Recommends => self.recommends.get(&package_num),
+            DependencyKind::Suggests => self.suggests.get(&package_num)
+        }
+    }
+
+    /// Parses a version from a loaded file, checking it against policy unless we're in lenient mode.
+    fn parse_version(&self, s: &str) -> Result<DebianVersionNum, RpkgError> {
+        if self.lenient_versions {
+            DebianVersionNum::parse_lenient(s)
+        } else {
+            s.parse::<DebianVersionNum>()
+        }
+    }
+
+    /// Switches version checking for subsequently loaded files between strict (policy) and lenient (legacy data).
+    pub fn set_version_mode(&mut self, lenient: bool) {
+        self.lenient_versions = lenient;
+        self.print_version_mode();
+    }
+
+    pub fn print_version_mode(&self) {
+        println!("Version checking: {}", if self.lenient_versions { "lenient" } else { "strict" });
+    }
+
+    /// Prints how v1 and v2 compare, after printing any policy rules they break. In strict mode, a version which
+    /// breaks a rule isn't compared; in lenient mode, it is compared anyway. Recommendations are only warned about.
+    pub fn print_version_compare(&self, v1: &str, v2: &str) -> Result<(), RpkgError> {
+        let mut valid = true;
+        for v in [v1, v2] {
+            if let Err(e) = DebianVersionNum::validate(v) {
+                println!("{}{}", if self.lenient_versions { "warning: " } else { "" }, e);
+                valid = false;
+            } else if let Some(w) = DebianVersionNum::warning(v) {
+                println!("warning: version {:?}: {}", v, w);
+            }
+        }
+        if !valid && !self.lenient_versions {
+            return Ok(());
+        }
+        let (v1, v2) = (DebianVersionNum::parse_lenient(v1)?, DebianVersionNum::parse_lenient(v2)?);
+        println!("{} and {}: {:?}", v1, v2, v1.cmp(&v2));
+        Ok(())
+    }
+
+    /// Returns true if version satisfies the version constraint (if any) in dep.
+    fn version_satisfies(&self, dep: &RelVersionedPackageNum, version: &DebianVersionNum) -> bool {
+        dep.rel_version.as_ref().is_none_or(|constraint| constraint.satisfied_by(version))
+    }
+
     // helper functions; these aren't structs so I can't make them implement Fmt::Display.
     fn deps2str(&self, deps: &Vec<Dependency>) -> String {
         return deps.iter().map(|dep| self.dep2str(dep)).format(", ").to_string();
     }
 
     fn dep2str(&self, dep: &Dependency) -> String {
-        return dep.iter().map(|d| {
-            let pn = self.get_package_name(d.package_num);
-            match &d.rel_version {
-                None => String::from(pn),
-                Some((rel, ver)) => format!("{} ({} {})", pn, rel.to_string(), ver)
-            }
-        }).format(" | ").to_string();
+        return dep.iter().map(|d| self.rel2str(d)).format(" | ").to_string();
     }
 
-    // output commands
-    pub fn print_deps(&self, package_name: &str) {
-        if !self.package_exists(package_name) {
-            println!("no such package {}", package_name);
-            return;
+    fn rel2str(&self, d: &RelVersionedPackageNum) -> String {
+        let mut s = String::from(self.get_package_name(d.package_num));
+        if let Some(q) = &d.arch_qualifier {
+            s.push_str(&format!(":{}", q));
         }
-        let deps : &Vec<Dependency> = &*self.dependencies.get(self.get_package_num(package_name)).unwrap();
-        println!("{:?} depends on {:?}", package_name, self.deps2str(deps));
+        if let Some(constraint) = &d.rel_version {
+            s.push_str(&format!(" ({})", constraint));
+        }
+        if !d.arch_restrictions.is_empty() {
+            let archs = d.arch_restrictions.iter().map(|(negated, a)| format!("{}{}", if *negated { "!" } else { "" }, a)).format(" ");
+            s.push_str(&format!(" [{}]", archs));
+        }
+        s
     }
 
-    pub fn print_transitive_dep_solution(&self, package_name: &str) {
-        if !self.package_exists(package_name) {
-            println!("no such package {}", package_name);
-            return;
-        }
-        let dep_solution : Vec<i32> = self.transitive_dep_solution(package_name);
-        println!("{:?} transitive dependency solution: {:?}", package_name, dep_solution.iter().map(|dep| self.get_package_name(*dep)).format(", ").to_string());
+    fn rels2str(&self, rels: &[RelVersionedPackageNum]) -> String {
+        rels.iter().map(|d| self.rel2str(d)).format(", ").to_string()
     }
 
-    pub fn print_how_to_install(&self, package_name: &str) {
-        if !self.package_exists(package_name) {
-            println!("no such package {}", package_name);
-            return;
+    // output commands
+    pub fn print_deps(&self, package_name: &str) -> Result<(), RpkgError> {
+        let package_num = self.get_package_num(package_name)?;
+        let deps : &Vec<Dependency> = &*self.dependencies.get(&package_num).unwrap();
+        println!("{:?} depends on {:?}", package_name, self.deps2str(deps));
+        for kind in [DependencyKind::PreDepends, DependencyKind::Recommends, DependencyKind::Suggests] {
+            if let Some(deps) = self.dependencies_of_kind(kind, package_num) {
+                println!("{:?} {} {:?}", package_name, kind.verb(), self.deps2str(deps));
+            }
         }
-        println!("Package {}:", package_name);
-        let pkgs_to_install:Vec<i32> = self.compute_how_to_install(package_name);
-        println!("{:?} to install: {:?}", package_name, pkgs_to_install.iter().map(|dep| self.get_package_name(*dep)).format(", ").to_string());
+        Ok(())
+    }
+
+    pub fn print_transitive_dep_solution(&self, package_names: &[&str]) -> Result<(), RpkgError> {
+        let package_nums = package_names.iter().map(|name| self.get_package_num(name)).collect::<Result<Vec<i32>, RpkgError>>()?;
+        let dep_solution : Vec<i32> = self.transitive_dep_solution(&package_nums);
+        println!("{:?} transitive dependency solution: {:?}", package_names.join(", "), dep_solution.iter().map(|dep| self.get_package_name(*dep)).format(", ").to_string());
+        Ok(())
     }
 
-    pub fn print_info(&self, package_name: &str) {
-        if !self.package_exists(package_name) {
-            println!("no such package {}", package_name);
-            return;
+    /// Prints how to install all of package_names together.
+    pub fn print_how_to_install(&self, package_names: &[&str], install_recommends: bool) -> Result<(), RpkgError> {
+        let package_nums = package_names.iter().map(|name| self.get_package_num(name)).collect::<Result<Vec<i32>, RpkgError>>()?;
+        let package_name = package_names.join(", ");
+        println!("{} {}:", if package_names.len() == 1 { "Package" } else { "Packages" }, package_name);
+        match self.compute_how_to_install(&package_nums, install_recommends) {
+            Ok(plan) => {
+                // name=version for packages which don't get their available version
+                let install = plan.install.iter().map(|dep| match (plan.versions.get(dep), self.available_debvers.get(dep)) {
+                    (Some(v), Some(av)) if v != av => format!("{}={}", self.get_package_name(*dep), v),
+                    _ => self.get_package_name(*dep).to_string()
+                });
+                println!("{:?} to install: {:?}", package_name, install.format(", ").to_string());
+                if !plan.remove.is_empty() {
+                    println!("{:?} to remove: {:?}", package_name, plan.remove.iter().map(|dep| self.get_package_name(*dep)).format(", ").to_string());
+                }
+                for cycle in &plan.cycles {
+                    println!("{:?} dependency cycle: {:?}", package_name, cycle.iter().map(|dep| self.get_package_name(*dep)).format(", ").to_string());
+                }
+            }
+            Err(RpkgError::Unsatisfiable(why)) =>
+                println!("{:?} cannot be installed: {}", package_name, why),
+            Err(e) => return Err(e)
         }
+        Ok(())
+    }
+
+    pub fn print_info(&self, package_name: &str) -> Result<(), RpkgError> {
+        let package_num = self.get_package_num(package_name)?;
         println!("Package: {}", package_name);
+        if let Some(arch) = self.architectures.get(&package_num) {
+            println!("Architecture: {}", arch);
+        }
+        if let Some(ma) = self.multi_arch.get(&package_num) {
+            println!("Multi-Arch: {}", ma);
+        }
         let a = self.get_available_debver(package_name);
         let i = self.get_installed_debver(package_name);
         match a {
             None => (),
             Some(a) => {
                 println!("Version: {}", a.to_string());
-                println!("MD5Sum: {}", self.get_md5sum(package_name).unwrap().to_string());
-                println!("Depends: {}", self.deps2str(&*self.dependencies.get(self.get_package_num(package_name)).unwrap()));
+                if self.candidates.get(&package_num).is_some_and(|c| c.len() > 1) {
+                    println!("Versions: {}", self.candidates2str(package_num));
+                }
+                if let Some(md5sum) = self.get_md5sum(package_name) {
+                    println!("MD5Sum: {}", md5sum);
+                }
+                if let Some(sha256) = self.get_sha256(package_name) {
+                    println!("SHA256: {}", sha256);
+                }
+                for kind in DependencyKind::ALL {
+                    if let Some(deps) = self.dependencies_of_kind(kind, package_num) {
+                        println!("{}: {}", kind.field_name(), self.deps2str(deps));
+                    }
+                }
+                for (field, rels) in [("Conflicts", self.conflicts.get(&package_num)), ("Breaks", self.breaks.get(&package_num)), ("Replaces", self.replaces.get(&package_num))] {
+                    if let Some(rels) = rels {
+                        println!("{}: {}", field, self.rels2str(rels));
+                    }
+                }
+                // the rest of the stanza, as it was in the Packages file
+                let printed = ["Package", "Architecture", "Multi-Arch", "Version", "MD5sum", "SHA256", "Pre-Depends", "Depends", "Recommends",
+                               "Suggests", "Conflicts", "Breaks", "Replaces"];
+                for (key, value) in self.records.get(&package_num).into_iter().flat_map(|r| r.fields()) {
+                    if !printed.iter().any(|p| p.eq_ignore_ascii_case(key)) {
+                        let separator = if value.starts_with('\n') { "" } else { " " };
+                        println!("{}:{}{}", key, separator, value);
+                    }
+                }
             }
         }
         match i {
             None => (),
-            Some(i) => { println!("Installed-Version: {}", i.to_string()) }
+            Some(i) => {
+                println!("Installed-Version: {}", i.to_string());
+                println!("Auto-Installed: {:?}", self.auto_installed.contains(&package_num));
+            }
         }
         match (a, i) {
             (Some(aa), Some(ii)) =>
                 { println!("Newer-Available: {:?}", aa > ii); }
             _ => ()
         }
+        Ok(())
     }
 
-    // generate output for package-verifier
-    pub fn output_md5s(&self, fname: &str) {
+    // generate output for package-verifier; with_sha256 adds a sha256 column (empty where we don't know it)
+    pub fn output_md5s(&self, fname: &str, with_sha256: bool) -> Result<(), RpkgError> {
         let path = Path::new(fname);
-        let mut md5s : String = "name,version,hash\n".to_owned();
+        let mut md5s : String = if with_sha256 { "name,version,hash,sha256\n" } else { "name,version,hash\n" }.to_owned();
         for pn in self.get_package_names() {
             match (self.get_available_debver(pn), self.get_md5sum(pn)) {
                 (Some(v), Some(m)) => {
-                    let row = format!("{},{},{}\n",pn,v.to_string(),m);
+                    let row = if with_sha256 {
+                        format!("{},{},{},{}\n", pn, v, m, self.get_sha256(pn).unwrap_or(""))
+                    } else {
+                        format!("{},{},{}\n",pn,v.to_string(),m)
+                    };
                     md5s.push_str(&row)
                 }
                 (_, _) => ()
             }
         }
-        std::fs::write(path, md5s).unwrap();
+        std::fs::write(path, md5s).map_err(|e| RpkgError::Io { file : fname.to_string(), source : e })
     }
 
-    // provided parse function to let students do the async io part independently
-    pub fn parse_csv(&mut self, filename: &str) {
-        let mut rdr = csv::Reader::from_path(filename).unwrap();
+    // provided parse function to let students do the async io part independently; reads the sha256 column too,
+    // if there is one
+    pub fn parse_csv(&mut self, filename: &str) -> Result<(), RpkgError> {
+        let mut rdr = csv::Reader::from_path(filename).map_err(|e| RpkgError::Csv { file : filename.to_string(), source : e })?;
+        let source = self.source_index(filename);
         for line in rdr.records() {
-            let line = line.unwrap();
-            let package_name = String::from(line.get(0).unwrap());
-            let debver = String::from(line.get(1).unwrap()).parse::<debversion::DebianVersionNum>().unwrap();
-            let md5sum = String::from(line.get(2).unwrap());
+            let line = line.map_err(|e| RpkgError::Csv { file : filename.to_string(), source : e })?;
+            let line_num = line.position().map_or(0, |p| p.line() as usize);
+            let field = |i: usize, name: &str| line.get(i).ok_or_else(|| RpkgError::MissingField(name.to_string()).at(filename, line_num, name));
+            let package_name = String::from(field(0, "name")?);
+            let debver = self.parse_version(field(1, "version")?).map_err(|e| e.at(filename, line_num, "version"))?;
+            let md5sum = String::from(field(2, "hash")?);
+            let sha256 = line.get(3).filter(|s| !s.is_empty()).map(String::from);
 
             let package_num = self.get_package_num_inserting(&package_name);
-            self.available_debvers.insert(package_num, debver);
-            self.md5sums.insert(package_num, md5sum);
+            self.add_csv_candidate(package_num, debver, md5sum, sha256, source);
 
         }
+        self.choose_candidates();
+        self.build_reverse_dependencies();
 
         println!("Packages available: {}", self.available_debvers.keys().len());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl Packages {
+    /// Loads the test fixture tests/fixtures/<name>.Packages and, if there is one, <name>.installed.
+    pub fn load_fixture(name: &str) -> Packages {
+        let mut p = Packages::new();
+        p.parse_packages(&fixture(&format!("{}.Packages", name))).unwrap();
+        let installed = fixture(&format!("{}.installed", name));
+        if Path::new(&installed).exists() {
+            p.parse_installed(&installed).unwrap();
+        }
+        p
+    }
+
+    /// The names of package_nums, sorted, for comparing in tests.
+    pub fn names(&self, package_nums: &[i32]) -> Vec<&str> {
+        let mut names : Vec<&str> = package_nums.iter().map(|pn| self.get_package_name(*pn)).collect();
+        names.sort_unstable();
+        names
+    }
+}
+
+/// The path of a file under tests/fixtures.
+#[cfg(test)]
+pub fn fixture(name: &str) -> String {
+    format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::Packages;
+    use super::fixture;
+
+    #[test]
+    fn output_md5s_can_add_a_sha256_column() {
+        let p = Packages::load_fixture("verify/checksums");
+        let out = std::env::temp_dir().join(format!("rpkg-output-md5s-{}.csv", std::process::id()));
+        p.output_md5s(out.to_str().unwrap(), true).unwrap();
+        let written = fs::read_to_string(&out).unwrap();
+        fs::remove_file(&out).unwrap();
+        // packages come out in no particular order, after the header
+        let sorted = |text: &str| {
+            let mut lines : Vec<String> = text.lines().map(String::from).collect();
+            lines[1..].sort();
+            lines
+        };
+        assert_eq!(sorted(&written), sorted(&fs::read_to_string(fixture("verify/checksums.csv")).unwrap()));
     }
 }
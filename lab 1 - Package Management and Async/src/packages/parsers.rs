@@ -2,90 +2,182 @@ use std::fs::File;
 use std::io::{self, BufRead};
 use std::path::Path;
 
+use flate2::bufread::MultiGzDecoder;
 use regex::Regex;
+use xz2::bufread::XzDecoder;
 
 use crate::Packages;
-use crate::packages::{Dependency, RelVersionedPackageNum};
+use crate::packages::{Candidate, Dependency, ProvidedPackage, RelVersionedPackageNum};
+use crate::packages::arch::MultiArch;
 
-use rpkg::debversion::{self, VersionRelation};
+use rpkg::debversion::{self, VersionConstraint};
+use rpkg::error::RpkgError;
 
-const KEYVAL_REGEX : &str = r"^(?P<key>(\w|-)+): (?P<value>.+)";
-const PKGNAME_AND_VERSION_REGEX : &str = r"(?P<pkg>(\w|\.|\+|-)+)( \((?P<op>(<|=|>)(<|=|>)?) (?P<ver>.*)\))?";
+pub const KEYVAL_REGEX : &str = r"^(?P<key>(\w|-)+):\s*(?P<value>.*)";
+const PKGNAME_AND_VERSION_REGEX : &str = r"(?P<pkg>(\w|\.|\+|-)+)(:(?P<archqual>(\w|-)+))?(\s*\((?P<op>(<|=|>)(<|=|>)?)\s*(?P<ver>[^)]*)\))?(\s*\[(?P<archs>[^\]]*)\])?";
 
 impl Packages {
     /// Loads packages and version numbers from a file, calling get_package_num_inserting on the package name
     /// and inserting the appropriate value into the installed_debvers map with the parsed version number.
-    pub fn parse_installed(&mut self, filename: &str) {
+    /// Also records Architecture and the Auto-Installed mark (packages without one count as manually installed).
+    pub fn parse_installed(&mut self, filename: &str) -> Result<(), RpkgError> {
         let kv_regexp = Regex::new(KEYVAL_REGEX).unwrap();
-        if let Ok(lines) = read_lines(filename) {
-            let mut current_package_num = 0;
-            for line in lines {
-                if let Ok(ip) = line {
-                     match kv_regexp.captures(&ip) {
-                        None => (),
-                        Some(caps) => {
-                            let (key, value) = (caps.name("key").unwrap().as_str(), caps.name("value").unwrap().as_str());
-                            current_package_num = match key {
-                                "Package" => self.get_package_num_inserting(value),
-                                _ => current_package_num
-                            };
-
-                            match key {
-                                "Version" => {
-                                    let debver = value.trim().parse::<debversion::DebianVersionNum>().unwrap();
-                                    self.installed_debvers.insert(current_package_num, debver);
-                                },
-                                _ => ()
-                            };
-                        }
+        let lines = read_lines(filename).map_err(|e| RpkgError::Io { file : filename.to_string(), source : e })?;
+        let mut current_package_num = 0;
+        for (line_num, line) in lines.enumerate() {
+            let ip = line.map_err(|e| RpkgError::Io { file : filename.to_string(), source : e })?;
+            match kv_regexp.captures(&ip) {
+                None => (),
+                Some(caps) => {
+                    let (key, value) = (caps.name("key").unwrap().as_str(), caps.name("value").unwrap().as_str());
+                    current_package_num = match key {
+                        "Package" => self.get_package_num_inserting(value),
+                        _ => current_package_num
+                    };
+
+                    match key {
+                        "Version" => {
+                            let debver = self.parse_version(value.trim())
+                                .map_err(|e| e.at(filename, line_num + 1, key))?;
+                            self.installed_debvers.insert(current_package_num, debver);
+                        },
+                        // as in apt's extended_states: installed only to satisfy a dependency, so autoremove may remove it
+                        "Auto-Installed" => {
+                            if value.trim() == "1" {
+                                self.auto_installed.insert(current_package_num);
+                            } else {
+                                self.auto_installed.remove(&current_package_num);
+                            }
+                        },
+                        // the available package's Architecture, if we know it, takes precedence
+                        "Architecture" => {
+                            self.architectures.entry(current_package_num).or_insert_with(|| value.trim().to_string());
+                        },
+                        _ => ()
+                    };
                 }
             }
         }
         println!("Packages installed: {}", self.installed_debvers.keys().len());
+        Ok(())
     }
-}
 
-    /// Loads packages, version numbers, dependencies, and md5sums from a file, calling get_package_num_inserting on the package name
-    /// and inserting the appropriate values into the dependencies, md5sum, and available_debvers maps.
-    pub fn parse_packages(&mut self, filename: &str) {
+    /// Loads packages, version numbers, architectures, dependencies (of every kind), conflicts, provides, and md5sums/SHA256s from a file,
+    /// calling get_package_num_inserting on the package name and adding each stanza as a Candidate (one version of the
+    /// package). Loading the same file again replaces what it said before. Then choose_candidates fills in the
+    /// architectures/multi_arch, dependencies (and pre_dependencies/recommends/suggests), conflicts/breaks/replaces,
+    /// provides/providers, md5sum/sha256sums, records, and available_debvers maps from each package's preferred candidate.
+    /// A file that doesn't parse changes none of them.
+    pub fn parse_packages(&mut self, filename: &str) -> Result<(), RpkgError> {
         let kv_regexp = Regex::new(KEYVAL_REGEX).unwrap();
         let pkgver_regexp = Regex::new(PKGNAME_AND_VERSION_REGEX).unwrap();
 
-        if let Ok(lines) = read_lines(filename) {
-            let mut current_package_num = 0;
-            for line in lines {
-                if let Ok(ip) = line {
-                    match kv_regexp.captures(&ip) {
-                        None => (),
-                        Some(cap) => {
-                            let (key, value) = (cap.name("key").unwrap().as_str(), cap.name("value").unwrap().as_str());
-                            current_package_num = match key {
-                                "Package" => self.get_package_num_inserting(value),
-                                _ => current_package_num
-                            }; //current package num match
-                            match key {
-                                "Version" => {
-                                    let debver = value.trim().parse::<debversion::DebianVersionNum>().unwrap();
-                                    self.available_debvers.insert(current_package_num, debver);
-                                },
-                                "MD5sum" => {
-                                    self.md5sums.insert(current_package_num, value.to_owned());
-                                },
-                                "Depends" => {
-                                    let ds = self.handle_dependencies(value, &current_package_num, &pkgver_regexp);
-                                    self.dependencies.insert(current_package_num, ds);
-                                },
-                                _ => ()
-                            }; // version match
-                        } //match on Some(cap)
+        let lines = read_lines(filename).map_err(|e| RpkgError::Io { file : filename.to_string(), source : e })?;
+        let source = self.source_index(filename);
+        let mut current_package_num = None;
+        let mut stanza : Vec<(String, String, usize)> = vec![]; // (key, value, line number) for the current package
+        // the file's candidates, which replace those it had before only once all of it has parsed
+        let mut parsed : Vec<(i32, Candidate)> = vec![];
+        for (line_num, line) in lines.enumerate() {
+            let ip = line.map_err(|e| RpkgError::Io { file : filename.to_string(), source : e })?;
+            match kv_regexp.captures(&ip) {
+                // a continuation line of a multi-line field, like Description
+                None if ip.starts_with(' ') || ip.starts_with('\t') => {
+                    if let Some((_, value, _)) = stanza.last_mut() {
+                        value.push('\n');
+                        value.push_str(&ip);
                     }
-
-                }
-            };
+                },
+                None => (),
+                Some(cap) => {
+                    let (key, value) = (cap.name("key").unwrap().as_str(), cap.name("value").unwrap().as_str());
+                    if key == "Package" {
+                        if let Some(package_num) = current_package_num {
+                            parsed.push((package_num, self.parse_stanza(&stanza, source, filename, &pkgver_regexp)?));
+                        }
+                        current_package_num = Some(self.get_package_num_inserting(value));
+                        stanza.clear();
+                    }
+                    stanza.push((key.to_string(), value.to_string(), line_num + 1));
+                } //match on Some(cap)
+            }
+        };
+        if let Some(package_num) = current_package_num {
+            parsed.push((package_num, self.parse_stanza(&stanza, source, filename, &pkgver_regexp)?));
+        }
+        self.forget_source(source);
+        for (package_num, candidate) in parsed {
+            self.add_candidate(package_num, candidate);
+        }
+        self.choose_candidates();
+        self.build_reverse_dependencies();
         println!("Packages available: {}", self.available_debvers.keys().len());
+        Ok(())
+    }
+
+    /// Turns the fields of one package's stanza into a Candidate for it. Every stanza needs a Version.
+    fn parse_stanza(&mut self, stanza: &[(String, String, usize)], source: usize, filename: &str, pkgver_regexp: &Regex) -> Result<Candidate, RpkgError> {
+        let version = match stanza.iter().find(|(key, _, _)| key == "Version") {
+            None => return Err(RpkgError::MissingField(String::from("Version")).at(filename, stanza[0].2, "Package")),
+            Some((key, value, line)) => self.parse_version(value.trim()).map_err(|e| e.at(filename, *line, key))?
+        };
+        let mut candidate = Candidate::new(version, source);
+        for (key, value, line) in stanza {
+            // the fields we interpret may be folded over several lines, which mean the same as one
+            let unfolded = value.lines().map(str::trim).filter(|l| !l.is_empty()).collect::<Vec<_>>().join(" ");
+            self.parse_packages_field(key, &unfolded, &mut candidate, pkgver_regexp)
+                .map_err(|e| e.at(filename, *line, key))?;
+            candidate.record.push(key, value);
         }
+        Ok(candidate)
     }
-    fn handle_dependencies(&mut self, depends:&str, current_package_num:&i32, pkgver_regexp:&Regex) -> Vec<Dependency>{
+
+    /// Stores the value of one field of the Packages stanza in candidate.
+    fn parse_packages_field(&mut self, key: &str, value: &str, candidate: &mut Candidate, pkgver_regexp: &Regex) -> Result<(), RpkgError> {
+        match key {
+            "MD5sum" => {
+                candidate.md5sum = Some(value.to_owned());
+            },
+            "SHA256" => {
+                candidate.sha256 = Some(value.trim().to_owned());
+            },
+            "Pre-Depends" | "Depends" | "Recommends" | "Suggests" => {
+                let ds = self.handle_dependencies(value, pkgver_regexp)?;
+                let field = match key {
+                    "Pre-Depends" => &mut candidate.pre_dependencies,
+                    "Depends" => &mut candidate.dependencies,
+                    "Recommends" => &mut candidate.recommends,
+                    _ => &mut candidate.suggests
+                };
+                *field = Some(ds);
+            },
+            "Architecture" => {
+                candidate.architecture = Some(value.trim().to_string());
+            },
+            "Multi-Arch" => {
+                candidate.multi_arch = Some(value.trim().parse::<MultiArch>()?);
+            },
+            "Provides" => {
+                let provided = self.handle_dependencies(value, pkgver_regexp)?.into_iter().flatten();
+                // only "=" is allowed in Provides; anything else counts as unversioned
+                candidate.provides = Some(provided.map(|rel| ProvidedPackage { package_num : rel.package_num,
+                                                                             version : rel.rel_version.and_then(|c| c.exact_version().cloned()) }).collect());
+            },
+            "Conflicts" | "Breaks" | "Replaces" => {
+                let rels = self.handle_dependencies(value, pkgver_regexp)?.into_iter().flatten().collect();
+                let field = match key {
+                    "Conflicts" => &mut candidate.conflicts,
+                    "Breaks" => &mut candidate.breaks,
+                    _ => &mut candidate.replaces
+                };
+                *field = Some(rels);
+            },
+            _ => ()
+        };
+        Ok(())
+    }
+
+    fn handle_dependencies(&mut self, depends:&str, pkgver_regexp:&Regex) -> Result<Vec<Dependency>, RpkgError> {
         let dependencies = depends.split(",");
         let mut returning :Vec<Dependency> = vec![];
 
@@ -97,43 +189,103 @@ impl Packages {
                     None => (),
                     Some(caps) => {
                         let pkg = caps.name("pkg").unwrap().as_str().to_string();
-                        let versionrelation:Option<(VersionRelation, String)> = match caps.name("op") {
-                            Some(matched) => {
-                                let op = caps
-                                    .name("op")
-                                    .unwrap()
-                                    .as_str()
-                                    .parse::<debversion::VersionRelation>()
-                                    .unwrap();
-                                let ver = caps
-                                    .name("ver")
-                                    .unwrap()
-                                    .as_str()
-                                    .to_string();
-                                Some((op, ver))
-                                //let relversionpackagenum = RelVersionedPackageNum{package_num : self.get_package_num_inserting(&pkg), rel_version: versionrelation};
+                        let versionrelation:Option<VersionConstraint> = match (caps.name("op"), caps.name("ver")) {
+                            (Some(op), Some(ver)) => {
+                                let op = op.as_str().parse::<debversion::VersionRelation>()?;
+                                let ver = self.parse_version(ver.as_str().trim())?;
+                                Some(VersionConstraint::new(op, ver))
                             },
-                            _ => None 
+                            _ => None
                         };
-                        let appending:RelVersionedPackageNum = RelVersionedPackageNum{ package_num : self.get_package_num_inserting(&pkg), rel_version : versionrelation};
+                        let arch_qualifier = caps.name("archqual").map(|q| q.as_str().to_string());
+                        // [amd64 !i386]: the architectures this alternative applies to (or, negated, doesn't)
+                        let arch_restrictions = match caps.name("archs") {
+                            None => vec![],
+                            Some(archs) => archs.as_str().split_whitespace().map(|a| match a.strip_prefix('!') {
+                                Some(negated) => (true, negated.to_string()),
+                                None => (false, a.to_string())
+                            }).collect()
+                        };
+                        let appending:RelVersionedPackageNum = RelVersionedPackageNum{ package_num : self.get_package_num_inserting(&pkg), rel_version : versionrelation,
+                                                                                       arch_qualifier, arch_restrictions };
 
                         test.push(appending);
 
                     }
                 }
             }
-            returning.push(test)
+            // an empty dependency (as in "a, , b") is no dependency at all
+            if !test.is_empty() {
+                returning.push(test)
+            }
         }
-        returning
+        Ok(returning)
     }
-    }
-
+}
 
+const GZIP_MAGIC : &[u8] = &[0x1f, 0x8b];
+const XZ_MAGIC : &[u8] = &[0xfd, b'7', b'z', b'X', b'Z', 0x00];
 
-// standard template code downloaded from the Internet somewhere
-fn read_lines<P>(filename: P) -> io::Result<io::Lines<io::BufReader<File>>>
+// standard template code downloaded from the Internet somewhere; extended to read gzip- and xz-compressed files
+// (like Packages.gz and Packages.xz on a mirror), recognized by their magic bytes rather than their names,
+// decompressing them as they are read
+pub fn read_lines<P>(filename: P) -> io::Result<io::Lines<Box<dyn BufRead>>>
 where P: AsRef<Path>, {
     let file = File::open(filename)?;
-    Ok(io::BufReader::new(file).lines())
+    let mut reader = io::BufReader::new(file);
+    let magic = reader.fill_buf()?;
+    let reader : Box<dyn BufRead> = if magic.starts_with(GZIP_MAGIC) {
+        Box::new(io::BufReader::new(MultiGzDecoder::new(reader)))
+    } else if magic.starts_with(XZ_MAGIC) {
+        Box::new(io::BufReader::new(XzDecoder::new_multi_decoder(reader)))
+    } else {
+        Box::new(reader)
+    };
+    Ok(reader.lines())
 }
 
+#[cfg(test)]
+mod tests {
+    use rpkg::error::RpkgError;
+
+    use crate::Packages;
+    use crate::packages::fixture;
+    use super::read_lines;
+
+    #[test]
+    fn keeps_multi_line_fields_together() {
+        let p = Packages::load_fixture("parsers/multiline");
+        let record = |name: &str| &p.records[&p.get_package_num(name).unwrap()];
+        assert_eq!(record("a").get("Description"), Some("short\n long line one\n .\n long line two"));
+        assert_eq!(record("a").get("Homepage"), Some("http://a.example.org/"));
+        assert_eq!(record("a").synopsis(), Some("short"));
+        // a field whose value starts on the next line is still a field of its own
+        assert_eq!(record("b").get("Version"), Some("1.0"));
+        assert_eq!(record("b").get("Description"), Some("\n only continuation\n more"));
+        assert_eq!(record("b").synopsis(), Some(""));
+        let deps = &p.dependencies[&p.get_package_num("b").unwrap()];
+        assert_eq!(p.deps2str(deps), "a, c");
+    }
+
+    #[test]
+    fn leaves_packages_alone_when_a_file_does_not_parse() {
+        let mut p = Packages::load_fixture("parsers/multiline");
+        let broken = fixture("parsers/broken.Packages");
+        match p.parse_packages(&broken) {
+            Err(RpkgError::Parse { file, line, field, .. }) => assert_eq!((file, line, field.as_str()), (broken, 11, "Multi-Arch")),
+            other => panic!("expected a parse error, got {:?}", other)
+        }
+        assert_eq!(p.get_available_debver("a").map(|v| v.to_string()), Some(String::from("1.0")));
+        assert_eq!(p.candidates[&p.get_package_num("a").unwrap()].len(), 1);
+        assert!(p.get_available_debver("c").is_none());
+    }
+
+    #[test]
+    fn reads_plain_gzipped_and_xz_compressed_files_alike() {
+        for name in ["parsers/lines.txt", "parsers/lines.txt.gz", "parsers/lines.txt.xz"] {
+            let lines : Vec<String> = read_lines(fixture(name)).unwrap().map(Result::unwrap).collect();
+            assert_eq!(lines, vec!["Package: a", "Version: 1.0"], "{}", name);
+        }
+        assert_eq!(read_lines(fixture("parsers/empty.txt")).unwrap().count(), 0);
+    }
+}
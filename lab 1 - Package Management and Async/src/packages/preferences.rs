@@ -0,0 +1,192 @@
+use regex::Regex;
+
+use rpkg::error::RpkgError;
+
+use crate::Packages;
+use crate::packages::Candidate;
+use crate::packages::parsers::{read_lines, KEYVAL_REGEX};
+use crate::packages::repo::ReleaseTag;
+use crate::packages::search::glob_to_regex;
+
+/// The priority of a version which no preference pins: as apt gives versions from an ordinary archive.
+pub const DEFAULT_PRIORITY : i32 = 500;
+/// The priority of an installed version which no loaded file makes available, as apt gives /var/lib/dpkg/status.
+pub const INSTALLED_PRIORITY : i32 = 100;
+/// The priority of a version from a release which says NotAutomatic, like backports.
+pub const NOT_AUTOMATIC_PRIORITY : i32 = 1;
+/// Versions at this priority or above may replace a newer installed version.
+pub const DOWNGRADE_PRIORITY : i32 = 1000;
+
+/// Which versions a preference pins: those matching a glob, or those from a release with the given fields (like
+/// a=stable, c=main), which only indexes loaded by load-repo have.
+enum Pin {
+    Version(Regex),
+    Release(Vec<(String, String)>)
+}
+
+/// One stanza of an apt preferences file: the packages it's about (names, globs, or /regexes/), the versions of
+/// them it pins, and the priority it gives those versions.
+pub struct Preference {
+    packages : Vec<Regex>,
+    specific : bool, // names only, no patterns: such stanzas take precedence over general ones
+    pin : Pin,
+    priority : i32
+}
+
+impl Preference {
+    fn matches(&self, package_name: &str, candidate: &Candidate, release: Option<&ReleaseTag>) -> bool {
+        self.packages.iter().any(|p| p.is_match(package_name)) && match &self.pin {
+            Pin::Version(version) => version.is_match(&candidate.version.to_string()),
+            Pin::Release(fields) => release.is_some_and(|r| fields.iter().all(|(k, v)| r.pin_field(k) == Some(v.as_str())))
+        }
+    }
+}
+
+/// Translates the Package field of a preference (or the version of a pin) into a regular expression: /.../ is a
+/// regular expression already, anything else a glob.
+fn pattern_to_regex(pattern: &str) -> Result<Regex, RpkgError> {
+    let re = match pattern.strip_prefix('/').and_then(|p| p.strip_suffix('/')) {
+        Some(re) => re.to_string(),
+        None => glob_to_regex(pattern)
+    };
+    Regex::new(&re).map_err(|e| RpkgError::BadPattern { pattern : pattern.to_string(), source : e })
+}
+
+impl Packages {
+    /// Loads an apt preferences file, replacing any preferences loaded before: stanzas of Package, Pin and
+    /// Pin-Priority, separated by blank lines (Explanation fields and comments are ignored). Version pins
+    /// ("Pin: version 2.36*") and release pins ("Pin: release a=stable-backports, c=main") are supported. Then sorts every package's candidates again by the new priorities.
+    pub fn parse_preferences(&mut self, filename: &str) -> Result<(), RpkgError> {
+        let kv_regexp = Regex::new(KEYVAL_REGEX).unwrap();
+        let lines = read_lines(filename).map_err(|e| RpkgError::Io { file : filename.to_string(), source : e })?;
+        let mut preferences = vec![];
+        let mut stanza : Vec<(String, String, usize)> = vec![]; // (key, value, line number) for the current stanza
+        for (line_num, line) in lines.enumerate() {
+            let ip = line.map_err(|e| RpkgError::Io { file : filename.to_string(), source : e })?;
+            if ip.trim().is_empty() {
+                if !stanza.is_empty() {
+                    preferences.push(parse_preference(&stanza, filename)?);
+                    stanza.clear();
+                }
+                continue
+            }
+            if ip.starts_with('#') {
+                continue
+            }
+            if let Some(cap) = kv_regexp.captures(&ip) {
+                stanza.push((cap.name("key").unwrap().as_str().to_string(), cap.name("value").unwrap().as_str().trim().to_string(), line_num + 1));
+            }
+        }
+        if !stanza.is_empty() {
+            preferences.push(parse_preference(&stanza, filename)?);
+        }
+        println!("Preferences loaded: {}", preferences.len());
+        self.preferences = preferences;
+        self.choose_candidates();
+        self.build_reverse_dependencies();
+        Ok(())
+    }
+
+    /// Returns the priority of a candidate of package_num: that of the first specific preference matching it, else
+    /// that of the first general one, else the default priority of its release (see ReleaseTag), else DEFAULT_PRIORITY.
+    pub fn pin_priority(&self, package_num: i32, candidate: &Candidate) -> i32 {
+        let name = self.get_package_name(package_num);
+        let release = self.releases.get(&candidate.source);
+        let mut matching = self.preferences.iter().filter(|p| p.matches(name, candidate, release));
+        let first = matching.clone().next();
+        let default = release.map_or(DEFAULT_PRIORITY, |r| r.default_priority());
+        matching.find(|p| p.specific).or(first).map_or(default, |p| p.priority)
+    }
+
+    /// Prints every version of package_name, newest first, with its priority and where it comes from, marking the
+    /// installed one with ***, like apt-cache policy; and which one is the candidate the solver prefers.
+    pub fn print_policy(&self, package_name: &str) -> Result<(), RpkgError> {
+        let package_num = self.get_package_num(package_name)?;
+        let installed = self.installed_debvers.get(&package_num);
+        println!("{}:", package_name);
+        println!("  Installed: {}", installed.map_or(String::from("(none)"), |v| v.to_string()));
+        println!("  Candidate: {}", self.available_debvers.get(&package_num).map_or(String::from("(none)"), |v| v.to_string()));
+        println!("  Version table:");
+        let mut table : Vec<&Candidate> = self.candidates.get(&package_num).into_iter().flatten().collect();
+        table.sort_by(|a, b| b.version.cmp(&a.version));
+        let mut installed_listed = false;
+        for c in table {
+            let is_installed = installed == Some(&c.version);
+            installed_listed |= is_installed;
+            println!(" {} {} {} ({})", if is_installed { "***" } else { "   " }, c.version, c.priority, self.source2str(c.source));
+        }
+        if let Some(v) = installed.filter(|_| !installed_listed) {
+            println!(" *** {} {} (installed)", v, INSTALLED_PRIORITY);
+        }
+        Ok(())
+    }
+}
+
+/// Turns one stanza of a preferences file into a Preference.
+fn parse_preference(stanza: &[(String, String, usize)], filename: &str) -> Result<Preference, RpkgError> {
+    let field = |key: &str| stanza.iter().find(|(k, _, _)| k.eq_ignore_ascii_case(key))
+        .ok_or_else(|| RpkgError::MissingField(key.to_string()).at(filename, stanza[0].2, &stanza[0].0));
+    let (key, packages, line) = field("Package")?;
+    let specific = !packages.contains(|c| "*?[/".contains(c));
+    let packages = packages.split_whitespace().map(pattern_to_regex).collect::<Result<Vec<Regex>, RpkgError>>()
+        .map_err(|e| e.at(filename, *line, key))?;
+    let (key, pin, line) = field("Pin")?;
+    let bad_pin = || RpkgError::BadPreference(format!("unsupported pin {:?}", pin)).at(filename, *line, key);
+    let pin = match pin.split_once(' ') {
+        Some(("version", version)) => Pin::Version(pattern_to_regex(version.trim()).map_err(|e| e.at(filename, *line, key))?),
+        Some(("release", fields)) => {
+            let fields = fields.split(',').map(|f| f.trim().split_once('=').map(|(k, v)| (k.trim().to_string(), v.trim().to_string())))
+                .collect::<Option<Vec<(String, String)>>>().ok_or_else(bad_pin)?;
+            if fields.iter().any(|(k, _)| !["a", "n", "c", "o", "l", "v"].contains(&k.as_str())) {
+                return Err(bad_pin());
+            }
+            Pin::Release(fields)
+        },
+        _ => return Err(bad_pin())
+    };
+    let (key, priority, line) = field("Pin-Priority")?;
+    let priority = priority.parse::<i32>()
+        .map_err(|_| RpkgError::BadPreference(format!("bad priority {:?}", priority)).at(filename, *line, key))?;
+    Ok(Preference { packages, specific, pin, priority })
+}
+
+#[cfg(test)]
+mod tests {
+    use rpkg::error::RpkgError;
+
+    use crate::Packages;
+    use crate::packages::fixture;
+    use super::DEFAULT_PRIORITY;
+
+    /// The priorities of package_name's candidates, by version.
+    fn priorities(p: &Packages, package_name: &str) -> Vec<(String, i32)> {
+        let mut priorities : Vec<(String, i32)> = p.candidates[&p.get_package_num(package_name).unwrap()].iter()
+            .map(|c| (c.version.to_string(), c.priority)).collect();
+        priorities.sort();
+        priorities
+    }
+
+    #[test]
+    fn specific_stanzas_win_then_the_first_general_one() {
+        let mut p = Packages::load_fixture("preferences/pins");
+        p.parse_preferences(&fixture("preferences/pins.prefs")).unwrap();
+        // libc6 2.33-1 doesn't match the specific stanza's version glob, so the general one applies
+        assert_eq!(priorities(&p, "libc6"), vec![(String::from("2.33-1"), 200), (String::from("2.36-1~bpo1"), 990)]);
+        assert_eq!(p.get_available_debver("libc6").map(|v| v.to_string()), Some(String::from("2.36-1~bpo1")));
+        assert_eq!(priorities(&p, "libz"), vec![(String::from("1.2.11-1"), 200)]);
+        assert_eq!(priorities(&p, "app"), vec![(String::from("1.0"), DEFAULT_PRIORITY)]);
+    }
+
+    #[test]
+    fn says_where_an_unsupported_pin_is() {
+        let mut p = Packages::load_fixture("preferences/pins");
+        let bad = fixture("preferences/bad.prefs");
+        match p.parse_preferences(&bad) {
+            Err(RpkgError::Parse { file, line, field, source }) => {
+                assert_eq!((file, line, field.as_str()), (bad, 3, "Pin"));
+                assert!(matches!(*source, RpkgError::BadPreference(_)));
+            },
+            other => panic!("expected a parse error, got {:?}", other)
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use rpkg::error::RpkgError;
+
+use crate::Packages;
+use crate::packages::{ProvidedPackage, RelVersionedPackageNum};
+
+impl Packages {
+    /// Returns true if the Provides entry provided satisfies dep. An unversioned dependency on a virtual package is
+    /// satisfied by any of its providers; a versioned one only by versioned Provides whose version satisfies it.
+    pub fn provide_satisfies(&self, dep: &RelVersionedPackageNum, provided: &ProvidedPackage) -> bool {
+        match (&dep.rel_version, &provided.version) {
+            (None, _) => true,
+            (Some(_), None) => false,
+            (Some(_), Some(v)) => self.version_satisfies(dep, v)
+        }
+    }
+
+    /// Returns the packages which provide dep.package_num in a way that satisfies dep.
+    pub fn providers_satisfying(&self, dep: &RelVersionedPackageNum) -> Vec<i32> {
+        match self.providers.get(&dep.package_num) {
+            None => vec![],
+            Some(ps) => ps.iter().filter(|p| self.provide_satisfies(dep, p)).map(|p| p.package_num).collect()
+        }
+    }
+
+    /// Returns the packages which provide dep.package_num, but not in a way that satisfies dep (i.e. unversioned or at the wrong version).
+    pub fn providers_not_satisfying(&self, dep: &RelVersionedPackageNum) -> Vec<i32> {
+        match self.providers.get(&dep.package_num) {
+            None => vec![],
+            Some(ps) => ps.iter().filter(|p| !self.provide_satisfies(dep, p)).map(|p| p.package_num).collect()
+        }
+    }
+
+    /// Prints the packages which provide virtual package package_name, with the provided version (if any)
+    /// and whether they are installed.
+    pub fn print_providers(&self, package_name: &str) -> Result<(), RpkgError> {
+        let package_num = self.get_package_num(package_name)?;
+        println!("Package {}:", package_name);
+        let providers = match self.providers.get(&package_num) {
+            None => { println!("-> not provided by any package"); return Ok(()) }
+            Some(ps) => ps
+        };
+        for p in providers {
+            let provider = self.get_package_name(p.package_num);
+            let version = match &p.version {
+                None => String::new(),
+                Some(v) => format!(" (= {})", v)
+            };
+            match self.installed_debvers.get(&p.package_num) {
+                None => println!("- {} provides {}{}", provider, package_name, version),
+                Some(iv) => println!("+ {} provides {}{}, installed version {}", provider, package_name, version, iv)
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Packages;
+    use crate::packages::RelVersionedPackageNum;
+
+    /// The (only) dependency of package_name.
+    fn dep<'a>(p: &'a Packages, package_name: &str) -> &'a RelVersionedPackageNum {
+        &p.dependencies[&p.get_package_num(package_name).unwrap()][0][0]
+    }
+
+    #[test]
+    fn any_provider_satisfies_an_unversioned_dependency() {
+        let p = Packages::load_fixture("providers/mta");
+        assert_eq!(p.names(&p.providers_satisfying(dep(&p, "mutt"))), ["exim4-daemon", "postfix"]);
+        assert_eq!(p.names(&p.providers_satisfying(dep(&p, "anymua"))), ["exim4-daemon", "postfix"]);
+        let plan = p.compute_how_to_install(&[p.get_package_num("mutt").unwrap()], false).unwrap();
+        assert_eq!(plan.install.len(), 1);
+    }
+
+    #[test]
+    fn only_a_versioned_provide_satisfies_a_versioned_dependency() {
+        let p = Packages::load_fixture("providers/mta");
+        assert_eq!(p.names(&p.providers_satisfying(dep(&p, "oldmua"))), ["exim4-daemon"]);
+        assert_eq!(p.names(&p.providers_not_satisfying(dep(&p, "oldmua"))), ["postfix"]);
+        assert!(p.providers_satisfying(dep(&p, "newmua")).is_empty());
+        let plan = p.compute_how_to_install(&[p.get_package_num("oldmua").unwrap()], false).unwrap();
+        assert_eq!(p.names(&plan.install), ["exim4-daemon"]);
+        assert!(p.compute_how_to_install(&[p.get_package_num("newmua").unwrap()], false).is_err());
+    }
+}
@@ -0,0 +1,100 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use itertools::Itertools;
+
+use rpkg::error::RpkgError;
+
+use crate::Packages;
+use crate::packages::DependencyKind;
+
+impl Packages {
+    /// Rebuilds reverse_dependencies from the dependencies of every kind: for each package, which packages
+    /// name it in some alternative, and in which field. Called when parse_packages finishes.
+    pub fn build_reverse_dependencies(&mut self) {
+        let mut index : HashMap<i32, Vec<(i32, DependencyKind)>> = HashMap::new();
+        for kind in DependencyKind::ALL {
+            for package_num in self.package_num_to_name.keys() {
+                for dep in self.dependencies_of_kind(kind, *package_num).into_iter().flatten() {
+                    for alt in dep {
+                        let rdeps = index.entry(alt.package_num).or_default();
+                        if !rdeps.contains(&(*package_num, kind)) {
+                            rdeps.push((*package_num, kind));
+                        }
+                    }
+                }
+            }
+        }
+        for rdeps in index.values_mut() {
+            let strength = |kind: &DependencyKind| DependencyKind::ALL.iter().position(|k| k == kind);
+            rdeps.sort_by(|(a, a_kind), (b, b_kind)| self.get_package_name(*a).cmp(self.get_package_name(*b)).then(strength(a_kind).cmp(&strength(b_kind))));
+        }
+        self.reverse_dependencies = index;
+    }
+
+    /// Returns the packages which depend directly on package_num, or on a virtual package it provides (which is
+    /// returned alongside), with the kind of dependency. With installed_only, only installed packages count.
+    pub fn direct_rdepends(&self, package_num: i32, installed_only: bool) -> Vec<(i32, DependencyKind, Option<i32>)> {
+        let via_provides = self.provides.get(&package_num).into_iter().flatten().map(|p| Some(p.package_num));
+        let mut result = vec![];
+        for via in std::iter::once(None).chain(via_provides) {
+            for (rdep, kind) in self.reverse_dependencies.get(&via.unwrap_or(package_num)).into_iter().flatten() {
+                if !installed_only || self.installed_debvers.contains_key(rdep) {
+                    result.push((*rdep, *kind, via));
+                }
+            }
+        }
+        result
+    }
+
+    /// Prints the packages which depend on package_name (or on a virtual package it provides), and how.
+    pub fn print_rdepends(&self, package_name: &str, installed_only: bool) -> Result<(), RpkgError> {
+        let package_num = self.get_package_num(package_name)?;
+        println!("Package {}:", package_name);
+        let rdeps = self.direct_rdepends(package_num, installed_only);
+        if rdeps.is_empty() {
+            println!("-> no reverse dependencies");
+        }
+        for (rdep, kind, via) in rdeps {
+            let target = via.unwrap_or(package_num);
+            let deps = self.dependencies_of_kind(kind, rdep).into_iter().flatten()
+                .filter(|dep| dep.iter().any(|alt| alt.package_num == target));
+            println!("- {} {} {:?}", self.get_package_name(rdep), kind.verb(), deps.map(|dep| self.dep2str(dep)).format(", ").to_string());
+        }
+        Ok(())
+    }
+
+    /// Prints every package which needs package_name, directly or through other packages, following Pre-Depends and
+    /// Depends (Recommends and Suggests don't stop anything from working). Nearest first.
+    pub fn print_rdepends_recursive(&self, package_name: &str, installed_only: bool) -> Result<(), RpkgError> {
+        let package_num = self.get_package_num(package_name)?;
+        let mut seen = HashSet::from([package_num]);
+        let mut worklist = VecDeque::from([package_num]);
+        let mut result = vec![];
+        while let Some(pn) = worklist.pop_front() {
+            for (rdep, kind, _) in self.direct_rdepends(pn, installed_only) {
+                if (kind == DependencyKind::PreDepends || kind == DependencyKind::Depends) && seen.insert(rdep) {
+                    result.push(rdep);
+                    worklist.push_back(rdep);
+                }
+            }
+        }
+        println!("{:?} recursive reverse dependencies: {:?}", package_name, result.iter().map(|pn| self.get_package_name(*pn)).format(", ").to_string());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Packages;
+    use crate::packages::DependencyKind;
+
+    #[test]
+    fn every_alternative_of_a_dependency_has_it_as_a_reverse_dependency() {
+        let p = Packages::load_fixture("rdepends/alternatives");
+        let rdepends = |name: &str| p.direct_rdepends(p.get_package_num(name).unwrap(), false).into_iter()
+            .map(|(rdep, kind, via)| (p.get_package_name(rdep), kind, via)).collect::<Vec<_>>();
+        assert!(rdepends("liba") == vec![("app", DependencyKind::Depends, None)]);
+        assert!(rdepends("libb") == vec![("app", DependencyKind::Depends, None), ("tool", DependencyKind::Recommends, None)]);
+        assert!(rdepends("app").is_empty());
+    }
+}
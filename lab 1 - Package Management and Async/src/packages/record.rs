@@ -0,0 +1,33 @@
+/// Every field of a package's stanza in a Packages file, in the order they appear, as text. The value of a multi-line
+/// field (like Description) is its first line followed by its continuation lines as they are in the file, i.e. each
+/// starting with a space, and " ." for an empty line. A field whose value starts on the next line has an empty first line.
+#[derive(Clone, Default)]
+pub struct PackageRecord {
+    fields : Vec<(String, String)>
+}
+
+impl PackageRecord {
+    pub fn new() -> PackageRecord {
+        PackageRecord::default()
+    }
+
+    /// Adds a field, replacing any earlier value of it.
+    pub fn push(&mut self, key: &str, value: &str) {
+        self.fields.retain(|(k, _)| !k.eq_ignore_ascii_case(key));
+        self.fields.push((key.to_string(), value.to_string()));
+    }
+
+    /// Returns the value of field key; field names are case-insensitive.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.fields.iter().find(|(k, _)| k.eq_ignore_ascii_case(key)).map(|(_, v)| v.as_str())
+    }
+
+    pub fn fields(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.fields.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// The synopsis: the first line of Description.
+    pub fn synopsis(&self) -> Option<&str> {
+        self.get("Description").and_then(|d| d.lines().next())
+    }
+}
@@ -0,0 +1,109 @@
+use std::collections::{HashSet, VecDeque};
+
+use itertools::Itertools;
+
+use rpkg::error::RpkgError;
+
+use crate::Packages;
+use crate::packages::DependencyKind;
+
+impl Packages {
+    /// Computes which other installed packages break, and so have to be removed too, if package_name is removed:
+    /// those with a Pre-Depends or Depends that is satisfied now, but wouldn't be without the removed packages.
+    /// Installed packages are assumed to have the dependencies listed for the available version.
+    /// Returns the removed packages, package_name first, then in the order in which they break.
+    pub fn compute_how_to_remove(&self, package_name: &str) -> Result<Vec<i32>, RpkgError> {
+        let package_num = self.get_package_num(package_name)?;
+        if !self.installed_debvers.contains_key(&package_num) {
+            return Err(RpkgError::NotInstalled(package_name.to_string()));
+        }
+        let mut removed = HashSet::from([package_num]);
+        let mut order = vec![package_num];
+        let mut worklist = VecDeque::from([package_num]);
+        while let Some(pn) = worklist.pop_front() {
+            for (rdep, kind, _) in self.direct_rdepends(pn, true) {
+                if kind != DependencyKind::PreDepends && kind != DependencyKind::Depends || removed.contains(&rdep) {
+                    continue
+                }
+                if self.breaks_without(rdep, &removed) {
+                    removed.insert(rdep);
+                    order.push(rdep);
+                    worklist.push_back(rdep);
+                }
+            }
+        }
+        Ok(order)
+    }
+
+    /// Returns true if some Pre-Depends or Depends of package_num is satisfied by the installed packages,
+    /// but not once the packages in removed are gone.
+    fn breaks_without(&self, package_num: i32, removed: &HashSet<i32>) -> bool {
+        let before = |pn: i32| self.installed_debvers.get(&pn);
+        let after = |pn: i32| if removed.contains(&pn) { None } else { self.installed_debvers.get(&pn) };
+        [DependencyKind::PreDepends, DependencyKind::Depends].iter()
+            .flat_map(|kind| self.dependencies_of_kind(*kind, package_num).into_iter().flatten())
+            .filter(|dep| self.dependency_applies(dep))
+            .any(|dep| self.goal_satisfied_with(dep, &before) && !self.goal_satisfied_with(dep, &after))
+    }
+
+    /// Finds the installed packages marked Auto-Installed which no manually installed package needs any more,
+    /// directly or indirectly, through Pre-Depends, Depends or Recommends (like apt, we keep what's recommended).
+    /// Every installed alternative (or provider) which satisfies a dependency counts as needed.
+    /// Packages in gone are treated as already removed. Returns the packages sorted by name.
+    pub fn compute_autoremove(&self, gone: &HashSet<i32>) -> Vec<i32> {
+        let present = |pn: &i32| self.installed_debvers.contains_key(pn) && !gone.contains(pn);
+        let mut needed : HashSet<i32> = self.installed_debvers.keys().filter(|pn| present(pn) && !self.auto_installed.contains(pn)).copied().collect();
+        let mut worklist : Vec<i32> = needed.iter().copied().collect();
+        while let Some(pn) = worklist.pop() {
+            for kind in [DependencyKind::PreDepends, DependencyKind::Depends, DependencyKind::Recommends] {
+                for alt in self.dependencies_of_kind(kind, pn).into_iter().flatten().flatten() {
+                    if !self.alternative_applies(alt) {
+                        continue
+                    }
+                    let itself = Some(alt.package_num).filter(|p| present(p) && self.arch_satisfies(alt, *p) &&
+                                                            self.version_satisfies(alt, self.installed_debvers.get(p).unwrap()));
+                    let providers = self.providers_satisfying(alt).into_iter().filter(|p| present(p) && self.arch_satisfies(alt, *p));
+                    for p in itself.into_iter().chain(providers) {
+                        if needed.insert(p) {
+                            worklist.push(p);
+                        }
+                    }
+                }
+            }
+        }
+        let mut unneeded : Vec<i32> = self.auto_installed.iter().filter(|pn| present(pn) && !needed.contains(pn)).copied().collect();
+        unneeded.sort_by(|a, b| self.get_package_name(*a).cmp(self.get_package_name(*b)));
+        unneeded
+    }
+
+    pub fn print_how_to_remove(&self, package_name: &str) -> Result<(), RpkgError> {
+        let removed = self.compute_how_to_remove(package_name)?;
+        println!("Package {}:", package_name);
+        println!("{:?} to remove: {:?}", package_name, removed[1..].iter().map(|pn| self.get_package_name(*pn)).format(", ").to_string());
+        let unneeded = self.compute_autoremove(&removed.into_iter().collect());
+        if !unneeded.is_empty() {
+            println!("{:?} then no longer needed: {:?}", package_name, unneeded.iter().map(|pn| self.get_package_name(*pn)).format(", ").to_string());
+        }
+        Ok(())
+    }
+
+    pub fn print_autoremove(&self) {
+        let unneeded = self.compute_autoremove(&HashSet::new());
+        println!("no longer needed: {:?}", unneeded.iter().map(|pn| self.get_package_name(*pn)).format(", ").to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Packages;
+
+    #[test]
+    fn removal_cascades_to_what_breaks_and_frees_what_they_needed() {
+        // other still has libzalt, and tool only recommends libz; libdep was only there for app
+        let p = Packages::load_fixture("removal/cascade");
+        let removed = p.compute_how_to_remove("libz").unwrap();
+        assert_eq!(removed.iter().map(|pn| p.get_package_name(*pn)).collect::<Vec<_>>(), ["libz", "liba", "app"]);
+        assert_eq!(p.names(&p.compute_autoremove(&removed.into_iter().collect())), ["libdep"]);
+        assert!(p.compute_autoremove(&Default::default()).is_empty());
+    }
+}
@@ -0,0 +1,282 @@
+use std::fs;
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+
+use rpkg::error::RpkgError;
+
+use crate::Packages;
+use crate::packages::preferences::{DEFAULT_PRIORITY, INSTALLED_PRIORITY, NOT_AUTOMATIC_PRIORITY};
+
+/// Where a Packages index came from, as its Release file says: the suite (archive) and codename, origin and label
+/// of the release, and the component it belongs to; and its default priority.
+pub struct ReleaseTag {
+    origin : Option<String>,
+    label : Option<String>,
+    suite : Option<String>,
+    codename : Option<String>,
+    version : Option<String>,
+    component : String,
+    priority : i32
+}
+
+impl ReleaseTag {
+    /// The priority of versions from this release which no preference pins: as in apt, a NotAutomatic release
+    /// (like backports) only gets 1, or 100 if it also says ButAutomaticUpgrades, and any other one DEFAULT_PRIORITY.
+    pub fn default_priority(&self) -> i32 {
+        self.priority
+    }
+
+    /// Returns the value of one of the keys of a release pin: a (archive, i.e. suite), n (codename), c (component),
+    /// o (origin), l (label) or v (version).
+    pub fn pin_field(&self, key: &str) -> Option<&str> {
+        match key {
+            "a" => self.suite.as_deref(),
+            "n" => self.codename.as_deref(),
+            "c" => Some(self.component.as_str()),
+            "o" => self.origin.as_deref(),
+            "l" => self.label.as_deref(),
+            "v" => self.version.as_deref(),
+            _ => None
+        }
+    }
+}
+
+/// The fields of a Release file, in order.
+struct Release {
+    fields : Vec<(String, String)>, // multi-line values (like SHA256) have their lines joined with "\n"
+}
+
+/// One line of a Release file's SHA256 field: an index file, relative to the Release file, with its size and hash.
+struct IndexEntry<'a> {
+    sha256 : &'a str,
+    size : u64,
+    path : &'a str
+}
+
+impl Release {
+    /// Parses the text of a Release file or, if it's clearsigned, of an InRelease file. The signature isn't checked.
+    fn parse(text: &str) -> Release {
+        let mut fields : Vec<(String, String)> = vec![];
+        let mut in_header = text.starts_with("-----BEGIN PGP SIGNED MESSAGE-----");
+        for line in text.lines() {
+            if in_header {
+                // the armor header lines (Hash: ...) end at the first blank line
+                in_header = !line.trim().is_empty();
+                continue
+            }
+            if line.starts_with("-----BEGIN PGP SIGNATURE-----") {
+                break
+            }
+            match line.chars().next() {
+                None => (),
+                Some(' ') | Some('\t') => {
+                    if let Some((_, value)) = fields.last_mut() {
+                        if !value.is_empty() {
+                            value.push('\n');
+                        }
+                        value.push_str(line.trim());
+                    }
+                },
+                Some(_) => {
+                    if let Some((key, value)) = line.split_once(':') {
+                        fields.push((key.trim().to_string(), value.trim().to_string()));
+                    }
+                }
+            }
+        }
+        Release { fields }
+    }
+
+    fn get(&self, key: &str) -> Option<&str> {
+        self.fields.iter().find(|(k, _)| k.eq_ignore_ascii_case(key)).map(|(_, v)| v.as_str())
+    }
+
+    fn list(&self, key: &str) -> Vec<&str> {
+        self.get(key).map_or(vec![], |v| v.split_whitespace().collect())
+    }
+
+    fn sha256_entries(&self) -> Vec<IndexEntry<'_>> {
+        self.get("SHA256").into_iter().flat_map(|v| v.lines()).filter_map(|line| {
+            match line.split_whitespace().collect::<Vec<&str>>()[..] {
+                [sha256, size, path] => size.parse().ok().map(|size| IndexEntry { sha256, size, path }),
+                _ => None
+            }
+        }).collect()
+    }
+
+    fn tag(&self, component: &str) -> ReleaseTag {
+        let field = |key: &str| self.get(key).map(|v| v.to_string());
+        let yes = |key: &str| self.get(key).is_some_and(|v| v.eq_ignore_ascii_case("yes"));
+        let priority = match (yes("NotAutomatic"), yes("ButAutomaticUpgrades")) {
+            (true, true) => INSTALLED_PRIORITY,
+            (true, false) => NOT_AUTOMATIC_PRIORITY,
+            _ => DEFAULT_PRIORITY
+        };
+        ReleaseTag { origin : field("Origin"), label : field("Label"), suite : field("Suite"), codename : field("Codename"),
+                     version : field("Version"), component : component.to_string(), priority }
+    }
+}
+
+/// Checks that the file at path has the size and SHA256 which the Release file lists for it.
+fn verify_index(path: &Path, entry: &IndexEntry) -> Result<(), RpkgError> {
+    let file = path.display().to_string();
+    let data = fs::read(path).map_err(|e| RpkgError::Io { file : file.clone(), source : e })?;
+    if data.len() as u64 != entry.size {
+        return Err(RpkgError::BadIndex { file, reason : format!("size is {}, but Release says {}", data.len(), entry.size) });
+    }
+    let sha256 = format!("{:x}", Sha256::digest(&data));
+    if !sha256.eq_ignore_ascii_case(entry.sha256) {
+        return Err(RpkgError::BadIndex { file, reason : format!("SHA256 is {}, but Release says {}", sha256, entry.sha256) });
+    }
+    Ok(())
+}
+
+impl Packages {
+    /// Loads the suites of the Debian repository at dir: the named ones, or else every suite under dir/dists.
+    pub fn load_repo(&mut self, dir: &str, suites: &[&str]) -> Result<(), RpkgError> {
+        let dists = Path::new(dir).join("dists");
+        let suites : Vec<String> = if suites.is_empty() {
+            let entries = fs::read_dir(&dists).map_err(|e| RpkgError::Io { file : dists.display().to_string(), source : e })?;
+            let mut found = vec![];
+            for entry in entries {
+                let entry = entry.map_err(|e| RpkgError::Io { file : dists.display().to_string(), source : e })?;
+                if entry.path().join("InRelease").exists() || entry.path().join("Release").exists() {
+                    found.push(entry.file_name().to_string_lossy().to_string());
+                }
+            }
+            found.sort();
+            found
+        } else {
+            suites.iter().map(|s| s.to_string()).collect()
+        };
+        for suite in suites {
+            self.load_suite(&dists.join(suite))?;
+        }
+        Ok(())
+    }
+
+    /// Reads the InRelease (or else Release) file in suite_dir and loads, with parse_packages, the Packages index of
+    /// each component it lists for each enabled architecture (and for "all"), preferring the xz-compressed, then the
+    /// gzip-compressed, then the plain one. An index is only loaded if its size and SHA256 are as the Release file says;
+    /// otherwise it's skipped with a message. The packages from each index are tagged with the suite and component.
+    fn load_suite(&mut self, suite_dir: &Path) -> Result<(), RpkgError> {
+        let release_path = ["InRelease", "Release"].iter().map(|f| suite_dir.join(f)).find(|p| p.exists())
+            .unwrap_or_else(|| suite_dir.join("Release"));
+        let release_file = release_path.display().to_string();
+        let text = fs::read_to_string(&release_path).map_err(|e| RpkgError::Io { file : release_file.clone(), source : e })?;
+        let release = Release::parse(&text);
+        let components = release.list("Components");
+        if components.is_empty() {
+            return Err(RpkgError::MissingField(String::from("Components")).at(&release_file, 1, "Components"));
+        }
+        let entries = release.sha256_entries();
+        let archs : Vec<&str> = release.list("Architectures").into_iter()
+            .filter(|a| *a == "all" || *a == self.native_arch || self.foreign_archs.iter().any(|f| f == a)).collect();
+        println!("Release {}: {} {}", release_file, release.get("Suite").or_else(|| release.get("Codename")).unwrap_or("?"),
+                 components.join(" "));
+        for component in &components {
+            for arch in &archs {
+                let index = ["Packages.xz", "Packages.gz", "Packages"].iter()
+                    .map(|f| format!("{}/binary-{}/{}", component, arch, f))
+                    .find_map(|path| entries.iter().find(|e| e.path == path).filter(|_| suite_dir.join(&path).exists()));
+                let entry = match index {
+                    None => {
+                        if *arch != "all" {
+                            println!("- no {}/binary-{} index listed in the Release file", component, arch);
+                        }
+                        continue
+                    },
+                    Some(entry) => entry
+                };
+                let path = suite_dir.join(entry.path);
+                if let Err(e) = verify_index(&path, entry) {
+                    println!("- rejected {}", e);
+                    continue
+                }
+                let filename = path.display().to_string();
+                self.parse_packages(&filename)?;
+                let source = self.source_index(&filename);
+                self.releases.insert(source, release.tag(component));
+            }
+        }
+        // the tags decide default priorities and which release pins match
+        self.choose_candidates();
+        self.build_reverse_dependencies();
+        Ok(())
+    }
+
+    /// Describes where candidates from source come from: suite/component for an index loaded by load-repo, else
+    /// the file name.
+    pub fn source2str(&self, source: usize) -> String {
+        match self.releases.get(&source) {
+            Some(tag) => format!("{}/{}", tag.suite.as_deref().or(tag.codename.as_deref()).unwrap_or("?"), tag.component),
+            None => self.sources[source].clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use rpkg::error::RpkgError;
+
+    use crate::Packages;
+    use crate::packages::fixture;
+    use crate::packages::preferences::{DEFAULT_PRIORITY, INSTALLED_PRIORITY};
+    use super::{Release, verify_index};
+
+    fn release(name: &str) -> Release {
+        Release::parse(&fs::read_to_string(fixture(name)).unwrap())
+    }
+
+    #[test]
+    fn parses_release_and_inrelease_files() {
+        let stable = release("repo/dists/stable/Release");
+        assert_eq!(stable.get("Suite"), Some("stable"));
+        assert_eq!(stable.list("Components"), vec!["main", "contrib", "non-free"]);
+        let paths : Vec<&str> = stable.sha256_entries().iter().map(|e| e.path).collect();
+        assert_eq!(paths, vec!["main/binary-amd64/Packages", "contrib/binary-amd64/Packages"]);
+        assert_eq!(stable.tag("main").default_priority(), DEFAULT_PRIORITY);
+
+        // the armor header's Hash: isn't a field, and nothing after the signature starts is
+        let backports = release("repo/dists/stable-backports/InRelease");
+        assert_eq!(backports.get("Hash"), None);
+        assert_eq!(backports.fields.first().map(|(k, _)| k.as_str()), Some("Origin"));
+        let entries = backports.sha256_entries();
+        assert_eq!(entries.iter().map(|e| (e.path, e.size)).collect::<Vec<_>>(),
+                   vec![("main/binary-amd64/Packages", 36), ("main/binary-amd64/Packages.xz", 100)]);
+        assert_eq!(backports.get("SHA256").unwrap().lines().count(), 2);
+        let tag = backports.tag("main");
+        assert_eq!(tag.default_priority(), INSTALLED_PRIORITY);
+        assert_eq!((tag.pin_field("n"), tag.pin_field("c")), (Some("bookworm-backports"), Some("main")));
+    }
+
+    #[test]
+    fn verifies_an_index_against_its_release_file() {
+        let suite = PathBuf::from(fixture("repo/dists/stable"));
+        let stable = release("repo/dists/stable/Release");
+        let entries = stable.sha256_entries();
+        assert!(verify_index(&suite.join(entries[0].path), &entries[0]).is_ok());
+        match verify_index(&suite.join(entries[1].path), &entries[1]) {
+            Err(RpkgError::BadIndex { reason, .. }) => assert!(reason.starts_with("SHA256 is "), "{}", reason),
+            other => panic!("expected a bad index, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn loads_only_the_verified_indexes_a_release_file_lists() {
+        let mut p = Packages::new();
+        p.load_repo(&fixture("repo"), &[]).unwrap();
+        let libc6 = &p.candidates[&p.get_package_num("libc6").unwrap()];
+        let versions : Vec<(String, i32, String)> = libc6.iter().map(|c| (c.version.to_string(), c.priority, p.source2str(c.source))).collect();
+        assert_eq!(versions, vec![(String::from("2.33-1"), DEFAULT_PRIORITY, String::from("stable/main")),
+                                  (String::from("2.36-1~bpo1"), INSTALLED_PRIORITY, String::from("stable-backports/main"))]);
+        assert!(p.get_available_debver("app").is_some());
+        // contrib's index has the wrong hash, and non-free's isn't in the Release file at all
+        assert!(p.get_available_debver("contribtool").is_none());
+        assert!(p.get_available_debver("nonfreetool").is_none());
+    }
+}
@@ -0,0 +1,146 @@
+use std::collections::HashSet;
+
+use regex::Regex;
+
+use rpkg::error::RpkgError;
+
+use crate::Packages;
+use crate::packages::DependencyKind;
+
+/// How the search command matches, and which packages it keeps; the filters all have to hold.
+#[derive(Default)]
+pub struct SearchOptions {
+    pub regex : bool, // the pattern is a regular expression, found anywhere, rather than a glob matching all of it
+    pub description : bool, // also match the pattern against the Description synopsis
+    pub installed : bool,
+    pub upgradable : bool,
+    pub missing_deps : bool // some Pre-Depends or Depends isn't satisfied by the installed packages
+}
+
+impl SearchOptions {
+    pub const FLAGS : [&'static str; 5] = ["--regex", "--description", "--installed", "--upgradable", "--missing-deps"];
+
+    /// Sets the option for one of FLAGS; returns false for anything else.
+    pub fn set_flag(&mut self, flag: &str) -> bool {
+        match flag {
+            "--regex" => self.regex = true,
+            "--description" => self.description = true,
+            "--installed" => self.installed = true,
+            "--upgradable" => self.upgradable = true,
+            "--missing-deps" => self.missing_deps = true,
+            _ => return false
+        }
+        true
+    }
+}
+
+/// Translates a glob (*, ?, [...] and [!...]) into a regular expression matching the whole string.
+pub fn glob_to_regex(glob: &str) -> String {
+    let mut re = String::from("^");
+    let mut in_class = false;
+    for (i, c) in glob.char_indices() {
+        match c {
+            '*' if !in_class => re.push_str(".*"),
+            '?' if !in_class => re.push('.'),
+            '[' if !in_class => { in_class = true; re.push('[') }
+            ']' if in_class => { in_class = false; re.push(']') }
+            '!' if in_class && glob[..i].ends_with('[') => re.push('^'),
+            '\\' | '^' | '[' if in_class => { re.push('\\'); re.push(c) }
+            _ if in_class => re.push(c),
+            _ => re.push_str(&regex::escape(&c.to_string()))
+        }
+    }
+    re.push('$');
+    re
+}
+
+impl Packages {
+    /// Returns the packages (those with an available or installed version, not virtual ones) whose name, or with
+    /// options.description whose synopsis, matches pattern and which pass the filters in options, sorted by name.
+    pub fn search(&self, pattern: &str, options: &SearchOptions) -> Result<Vec<i32>, RpkgError> {
+        let re = if options.regex { pattern.to_string() } else { glob_to_regex(pattern) };
+        let re = Regex::new(&re).map_err(|e| RpkgError::BadPattern { pattern : pattern.to_string(), source : e })?;
+        let upgradable : HashSet<i32> = if options.upgradable { self.upgradable().into_iter().collect() } else { HashSet::new() };
+
+        let mut names = self.get_package_names();
+        names.sort_unstable();
+        let mut result = vec![];
+        for name in names {
+            let package_num = self.get_package_num(name)?;
+            let matches = re.is_match(name) ||
+                (options.description && self.records.get(&package_num).and_then(|r| r.synopsis()).is_some_and(|d| re.is_match(d)));
+            let known = self.available_debvers.contains_key(&package_num) || self.installed_debvers.contains_key(&package_num);
+            if !matches || !known ||
+                (options.installed && !self.installed_debvers.contains_key(&package_num)) ||
+                (options.upgradable && !upgradable.contains(&package_num)) ||
+                (options.missing_deps && !self.has_missing_deps(package_num)) {
+                continue
+            }
+            result.push(package_num);
+        }
+        Ok(result)
+    }
+
+    /// Returns true if some applicable Pre-Depends or Depends of package_num isn't satisfied by the installed packages.
+    fn has_missing_deps(&self, package_num: i32) -> bool {
+        [DependencyKind::PreDepends, DependencyKind::Depends].iter()
+            .flat_map(|kind| self.dependencies_of_kind(*kind, package_num).into_iter().flatten())
+            .any(|dep| self.dependency_applies(dep) && self.dep_is_satisfied(dep).is_none())
+    }
+
+    /// Prints the packages search finds, installed ones marked with +, with their (available, else installed)
+    /// version and synopsis.
+    pub fn print_search(&self, pattern: &str, options: &SearchOptions) -> Result<(), RpkgError> {
+        let found = self.search(pattern, options)?;
+        if found.is_empty() {
+            println!("-> no packages found");
+        }
+        for package_num in found {
+            let installed = self.installed_debvers.get(&package_num);
+            let version = self.available_debvers.get(&package_num).or(installed).unwrap();
+            let mark = if installed.is_some() { "+" } else { "-" };
+            match self.records.get(&package_num).and_then(|r| r.synopsis()) {
+                None => println!("{} {} {}", mark, self.get_package_name(package_num), version),
+                Some(d) => println!("{} {} {}: {}", mark, self.get_package_name(package_num), version, d)
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use regex::Regex;
+
+    use super::glob_to_regex;
+
+    fn glob_matches(glob: &str, s: &str) -> bool {
+        Regex::new(&glob_to_regex(glob)).unwrap().is_match(s)
+    }
+
+    #[test]
+    fn stars_and_question_marks() {
+        assert!(glob_matches("lib*", "libc6") && glob_matches("lib*", "lib"));
+        assert!(!glob_matches("lib*", "xlib"));
+        assert!(glob_matches("lib?", "libz"));
+        assert!(!glob_matches("lib?", "lib") && !glob_matches("lib?", "libzz"));
+    }
+
+    #[test]
+    fn character_classes() {
+        assert!(glob_matches("lib[cz]6", "libc6") && glob_matches("lib[cz]6", "libz6"));
+        assert!(!glob_matches("lib[cz]6", "liba6"));
+        assert!(glob_matches("lib[!cz]*", "liba"));
+        assert!(!glob_matches("lib[!cz]*", "libc6"));
+        assert!(glob_matches("[a-c]", "b") && !glob_matches("[a-c]", "d"));
+    }
+
+    #[test]
+    fn escapes_regex_metacharacters() {
+        assert_eq!(glob_to_regex("a.b"), r"^a\.b$");
+        assert!(glob_matches("libstdc++6", "libstdc++6"));
+        assert!(!glob_matches("libstdc++6", "libstdcc6"));
+        assert!(glob_matches("g(x)|$", "g(x)|$"));
+        assert!(glob_matches("[+.]", "+") && !glob_matches("[+.]", "x"));
+    }
+}
@@ -1,154 +1,671 @@
-use rpkg::debversion;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use itertools::Itertools;
+
+use rpkg::debversion::{DebianVersionNum, VersionConstraint};
+use rpkg::error::RpkgError;
 
 use crate::Packages;
-use crate::packages::Dependency;
+use crate::packages::{Dependency, DependencyKind};
 
 use super::RelVersionedPackageNum;
 
 impl Packages {
-    /// Computes a solution for the transitive dependencies of package_name; when there is a choice A | B | C, 
-    /// chooses the first option A. Returns a Vec<i32> of package numbers.
+    /// Computes a solution for the transitive dependencies of package_nums, together; when there is a choice
+    /// A | B | C, chooses the first option A. Returns a Vec<i32> of package numbers, nearest first.
     ///
     /// Note: does not consider which packages are installed.
-        
-    pub fn transitive_dep_solution(&self, package_name: &str) -> Vec<i32> {
-        if !self.package_exists(package_name) {
-            return vec![];
-        }
-
-        let deps: &Vec<Dependency> = &*self.dependencies.get(self.get_package_num(package_name)).unwrap();
+    ///
+    /// A breadth-first work-list: each package is added to the visited set when it is first found, and its
+    /// dependencies are looked at once, when it comes off the work-list, so this is linear in the size of the closure.
+    pub fn transitive_dep_solution(&self, package_nums: &[i32]) -> Vec<i32> {
         let mut dependency_set = vec![];
-
-        for dep_list in deps {
-            for dep in dep_list {
-                let pkg_num = dep_list[0].package_num;
-                if !dependency_set.contains(&pkg_num) {
+        let mut visited = HashSet::new();
+        let mut worklist = VecDeque::from(package_nums.to_vec());
+        while let Some(pn) = worklist.pop_front() {
+            for dep in self.dependencies.get(&pn).into_iter().flatten() {
+                let pkg_num = dep[0].package_num;
+                if visited.insert(pkg_num) {
                     dependency_set.push(pkg_num);
+                    worklist.push_back(pkg_num);
+                }
             }
+        }
+        dependency_set
+    }
+
+    /// Computes a set of packages that need to be installed to satisfy the deps of package_nums given the current installed packages.
+    /// This is a backtracking search over the dependencies map: every unsatisfied dependency A | B | C is a choice point,
+    /// and the alternatives are tried in this order:
+    ///   (1) alternatives which are installed, but with the wrong version, and whose available version would fix that
+    ///       (highest installed version first, i.e. upgrade something we already have);
+    ///   (2) the remaining alternatives, by the pin priority of their preferred versions, and then highest available
+    ///       version first (yes, still comparing apples and oranges);
+    /// each with its candidate versions in order of preference (see choose_candidates), so that a package that's available in several versions
+    /// (say, from stable and from backports) gets the preferred one unless a dependency needs another.
+    /// Choosing an alternative fixes its version, which every dependency seen so far must still accept, and which must not conflict with (or break) anything installed or chosen; if no alternative works,
+    /// we undo the most recent choice and try its next alternative.
+    ///
+    /// Every dependency on a single package also narrows down the versions that package may end up at; a choice that
+    /// leaves none (say, >= 2.33 and << 2.32) is abandoned right away rather than searched below.
+    ///
+    /// Pre-Depends count as dependencies, too. With install_recommends, Recommends are also goals, but soft ones:
+    /// when a Recommends can't be satisfied, the solver just skips it.
+    ///
+    /// Several packages are solved jointly, as one consistent set that installs all of them.
+    ///
+    /// Installed packages have to keep working: those of their Pre-Depends and Depends which hold now are goals too, so
+    /// the solver won't upgrade or replace a package that one of them needs (see push_installed_goals).
+    ///
+    /// Returns the packages to install in dependency order, i.e. each after the packages it (pre-)depends on, with
+    /// dependency cycles grouped together and reported (plus any installed packages that get replaced), or an
+    /// explanation of why there is no consistent set.
+    pub fn compute_how_to_install(&self, package_nums: &[i32], install_recommends: bool) -> Result<InstallPlan, RpkgError> {
+        let mut plan = self.solve(package_nums, install_recommends).map_err(RpkgError::Unsatisfiable)?;
+        plan.install.retain(|pn| !package_nums.contains(pn));
+        Ok(plan)
+    }
+
+    /// Finds a consistent set of packages to install so that all of roots are installed at their available versions.
+    pub fn solve(&self, roots: &[i32], install_recommends: bool) -> Result<InstallPlan, String> {
+        let mut state = SolverState::new(self.conflicts_index(), install_recommends);
+        self.push_installed_goals(&mut state);
+        for root in roots {
+            let root_version = match self.available_debvers.get(root) {
+                None => return Err(format!("{} has no available version", self.get_package_name(*root))),
+                Some(v) => v
+            };
+            if !self.arch_installable(*root) {
+                return Err(format!("{} is for architecture {}, which is not enabled", self.get_package_name(*root), self.architectures.get(root).unwrap()));
             }
+            // the roots are installed at their available versions, too, so dependency cycles back to them are checked
+            let removals = self.conflicts_with_solution(&state, *root, root_version)?;
+            state.select(*root, root_version, removals);
+            self.push_goals(&mut state, *root)?;
         }
 
-        loop {
-            let mut new_dependencies = Vec::new();
-            for package_num in &dependency_set {
-                let deps = &*self.dependencies.get(&package_num).unwrap();
+        let mut choice_points : Vec<ChoicePoint> = vec![];
+        let mut next_alternative = 0;
+        let mut backtracks = 0;
+        // the unsatisfiable goal seen furthest into the search, used to explain failure
+        let mut deepest_failure : Option<(usize, i32, &Dependency)> = None;
+        // why choosing a package led to impossible version requirements, also used to explain failure
+        let mut pruned : HashMap<i32, String> = HashMap::new();
 
-                for dep in deps {
-                    let pkg_num = dep[0].package_num;
-                    if !dependency_set.contains(&pkg_num) {
-                        new_dependencies.push(pkg_num);
+        while state.next_goal < state.goals.len() {
+            let goal = state.next_goal;
+            let (needed_by, dependency, soft) = state.goals[goal];
+            if next_alternative == 0 && (state.goal_is_moot(goal) || self.goal_is_satisfied(&state, dependency)) {
+                state.next_goal += 1;
+                continue
+            }
+
+            let alternatives = self.order_alternatives(dependency);
+            let viable = alternatives.iter().enumerate().skip(next_alternative)
+                .find_map(|(i, (candidate, alt, version))| self.alternative_is_viable(&state, *candidate, alt, version, goal).map(|removals| (i, *candidate, *version, removals)));
+            match viable {
+                Some((i, candidate, version, removals)) => {
+                    let choice_point = ChoicePoint { goal, next_alternative: i + 1, selected_len: state.order.len(),
+                                                     removed_len: state.removal_order.len(), goals_len: state.goals.len(),
+                                                     required_len: state.required_log.len() };
+                    state.select(candidate, version, removals);
+                    match self.push_goals(&mut state, candidate) {
+                        Ok(()) => {
+                            choice_points.push(choice_point);
+                            state.next_goal += 1;
+                            next_alternative = 0;
+                        }
+                        Err(why) => {
+                            // no need to search below this choice: try the next alternative straight away
+                            pruned.insert(candidate, why);
+                            state.undo(&choice_point);
+                            next_alternative = choice_point.next_alternative;
+                        }
+                    }
+                }
+                None if soft => {
+                    // an unsatisfiable Recommends isn't worth backtracking over
+                    state.next_goal += 1;
+                    next_alternative = 0;
+                }
+                None => {
+                    if deepest_failure.is_none_or(|(d, _, _)| goal >= d) {
+                        deepest_failure = Some((goal, needed_by, dependency));
                     }
+                    let (failed_goal, failed_pkg, failed_dep) = deepest_failure.unwrap();
+                    let choice_point = match choice_points.pop() {
+                        None => {
+                            let notes = failed_dep.iter().filter_map(|alt| pruned.get(&alt.package_num)).format("; ").to_string();
+                            let mut why = self.explain_failure(failed_pkg, failed_dep);
+                            if failed_goal < state.installed_goals {
+                                why = format!("would break installed {}: {}", self.get_package_name(failed_pkg), why);
+                            }
+                            return Err(if notes.is_empty() { why } else { format!("{} ({})", why, notes) });
+                        }
+                        Some(cp) => cp
+                    };
+                    backtracks += 1;
+                    if backtracks > MAX_BACKTRACKS {
+                        return Err(format!("gave up after {} backtracks; deepest unsatisfiable dependency was {}",
+                                           MAX_BACKTRACKS, self.describe_goal(failed_pkg, failed_dep)));
+                    }
+                    state.undo(&choice_point);
+                    next_alternative = choice_point.next_alternative;
                 }
             }
+        }
 
-            if new_dependencies.is_empty() {
-                break;
+        let (install, cycles) = self.install_order(&state.order, &|pn| self.version_in_solution(&state, pn));
+        let versions = state.selected.iter().map(|(pn, v)| (*pn, (*v).clone())).collect();
+        Ok(InstallPlan { install, versions, remove: state.removal_order, cycles })
+    }
+
+    /// Adds the Pre-Depends and Depends of every installed package, at its installed version, which are satisfied now
+    /// (those which aren't are not ours to fix) to the goals. They come first, so that every later choice has to
+    /// keep them satisfied; see SolverState::goal_is_moot for when they stop counting.
+    fn push_installed_goals<'a>(&'a self, state: &mut SolverState<'a>) {
+        let mut installed : Vec<i32> = self.installed_debvers.keys().copied().collect();
+        installed.sort_unstable();
+        let now = |pn: i32| self.installed_debvers.get(&pn);
+        for package_num in installed {
+            for kind in [DependencyKind::PreDepends, DependencyKind::Depends] {
+                for dep in self.dependencies_of_version(kind, package_num, now(package_num)).into_iter().flatten()
+                    .filter(|dep| self.dependency_applies(dep) && self.goal_satisfied_with(dep, &now)) {
+                    state.goals.push((package_num, dep, false));
+                }
             }
-            dependency_set.extend(new_dependencies);
         }
+        state.installed_goals = state.goals.len();
+    }
 
-        return dependency_set;
+    /// Adds package_num's Pre-Depends and Depends (and Recommends, as soft goals, if we're installing them), at the
+    /// version it was selected at, to the list of goals the solver must satisfy.
+    /// Fails if that makes the version requirements on some package impossible to meet; see require_versions.
+    fn push_goals<'a>(&'a self, state: &mut SolverState<'a>, package_num: i32) -> Result<(), String> {
+        for kind in [DependencyKind::PreDepends, DependencyKind::Depends, DependencyKind::Recommends] {
+            if kind == DependencyKind::Recommends && !state.install_recommends {
+                continue
+            }
+            // dependencies which are restricted to other architectures don't matter here
+            let version = state.selected.get(&package_num).copied();
+            for dep in self.dependencies_of_version(kind, package_num, version).into_iter().flatten().filter(|dep| self.dependency_applies(dep)) {
+                state.goals.push((package_num, dep, kind == DependencyKind::Recommends));
+                if kind != DependencyKind::Recommends {
+                    self.require_versions(state, package_num, dep)?;
+                }
+            }
+        }
+        Ok(())
     }
 
-    /// Computes a set of packages that need to be installed to satisfy package_name's deps given the current installed packages.
-    /// When a dependency A | B | C is unsatisfied, there are two possible cases:
-    ///   (1) there are no versions of A, B, or C installed; pick the alternative with the highest version number (yes, compare apples and oranges).
-    ///   (2) at least one of A, B, or C is installed (say A, B), but with the wrong version; of the installed packages (A, B), pick the one with the highest version number.
-    pub fn compute_how_to_install(&self, package_name: &str) -> Vec<i32> {
-        if !self.package_exists(package_name) {
-            return vec![];
+    /// A hard dependency whose alternatives all name the same real package X, like "X (>= 1.2)" or
+    /// "X (<< 1.0) | X (>= 2.0)", means that X must end up installed at a version in the union of their constraints.
+    /// Intersects that with what earlier dependencies required of X, and fails if nothing is left, or if none of the
+    /// versions X could end up at (the chosen one, or else the installed one and the candidates) is left.
+    fn require_versions(&self, state: &mut SolverState, needed_by: i32, dependency: &Dependency) -> Result<(), String> {
+        let alternatives : Vec<&RelVersionedPackageNum> = dependency.iter().filter(|alt| self.alternative_applies(alt)).collect();
+        let package_num = match alternatives.first() {
+            None => return Ok(()),
+            Some(alt) => alt.package_num
+        };
+        if self.providers.contains_key(&package_num) || alternatives.iter().any(|alt| alt.package_num != package_num) {
+            return Ok(());
+        }
+        let constraint = alternatives.iter()
+            .fold(VersionConstraint::none(), |c, alt| c.union(alt.rel_version.as_ref().unwrap_or(&VersionConstraint::any())));
+        let previous = state.required.get(&package_num).cloned();
+        let (required, mut needed_by_all) = match &previous {
+            None => (constraint.clone(), vec![]),
+            Some((c, by)) => (c.intersection(&constraint), by.clone())
+        };
+        needed_by_all.push(needed_by);
+        let name = self.get_package_name(package_num);
+        if required.is_empty() {
+            let (earlier, earlier_by) = previous.unwrap();
+            return Err(format!("conflicting requirements on {}: {} (for {}) and {} (for {})", name, constraint, self.get_package_name(needed_by),
+                               earlier, earlier_by.iter().map(|pn| self.get_package_name(*pn)).format(", ")));
         }
-        let package_num = self.get_package_num(package_name);
-        let dependencies = match self.dependencies.get(&package_num) {
-            None => {return vec![*package_num]},
-            Some(dependencies) => dependencies
+        let mut possible_versions : Vec<&DebianVersionNum> = match state.selected.get(&package_num) {
+            Some(v) => vec![*v],
+            None => self.installed_debvers.get(&package_num).filter(|_| !state.removed.contains(&package_num)).into_iter()
+                .chain(self.candidate_versions(package_num)).collect()
         };
+        possible_versions.sort();
+        possible_versions.dedup();
+        if !possible_versions.iter().any(|v| required.satisfied_by(v)) {
+            return Err(format!("no version of {} satisfies {} (for {}); possible versions: {}", name, required,
+                               needed_by_all.iter().map(|pn| self.get_package_name(*pn)).format(", "),
+                               if possible_versions.is_empty() { String::from("none") } else { possible_versions.iter().format(", ").to_string() }));
+        }
+        state.required_log.push((package_num, previous));
+        state.required.insert(package_num, (required, needed_by_all));
+        Ok(())
+    }
+
+    /// Returns the version plan installs package_num at: the version the solver chose, or else the available one.
+    pub fn planned_version<'a>(&'a self, plan: &'a InstallPlan, package_num: i32) -> Option<&'a DebianVersionNum> {
+        plan.versions.get(&package_num).or_else(|| self.available_debvers.get(&package_num))
+    }
+
+    /// Returns the version package_num would have if the current partial solution were installed.
+    fn version_in_solution<'a>(&'a self, state: &SolverState<'a>, package_num: i32) -> Option<&'a DebianVersionNum> {
+        if state.removed.contains(&package_num) {
+            return None;
+        }
+        match state.selected.get(&package_num) {
+            Some(v) => Some(*v),
+            None => self.installed_debvers.get(&package_num)
+        }
+    }
+
+    fn goal_is_satisfied(&self, state: &SolverState, dependency: &Dependency) -> bool {
+        self.goal_satisfied_with(dependency, &|pn| self.version_in_solution(state, pn))
+    }
+
+    /// Returns true if some alternative of dependency (or some package providing one) is present at the version
+    /// given by version_of, which maps a package to the version it would have, or None if it wouldn't be installed.
+    pub fn goal_satisfied_with<'a>(&'a self, dependency: &Dependency, version_of: &dyn Fn(i32) -> Option<&'a DebianVersionNum>) -> bool {
+        dependency.iter().any(|alt| {
+            (self.arch_satisfies(alt, alt.package_num) && version_of(alt.package_num).is_some_and(|v| self.version_satisfies(alt, v))) ||
+                self.providers_satisfying(alt).into_iter().any(|p| self.arch_satisfies(alt, p) && version_of(p).is_some())
+        })
+    }
 
-        let mut dependencies_to_add : Vec<i32> = vec![];
-        // implement more sophisticated worklist
-        for dependency_group in dependencies {
-            if let Some(pkg_satisfying) = self.dep_is_satisfied(dependency_group) {
+    /// An alternative is viable if candidate (either the alternative itself, or a package providing it) satisfies
+    /// the dependency at version, version meets what the hard goals so far require of candidate, it doesn't conflict
+    /// with the solution so far, and switching to that version (and removing whatever it replaces) doesn't break any
+    /// of the goals which were already satisfied before goal.
+    /// Returns the installed packages which the candidate would replace, or None if it isn't viable.
+    fn alternative_is_viable(&self, state: &SolverState, candidate: i32, alt: &RelVersionedPackageNum, version: &DebianVersionNum, goal: usize) -> Option<Vec<i32>> {
+        if state.selected.contains_key(&candidate) || state.removed.contains(&candidate) {
+            // already chosen at a version which doesn't satisfy this goal; or replaced
+            return None;
+        }
+        if state.required.get(&candidate).is_some_and(|(required, _)| !required.satisfied_by(version)) {
+            return None;
+        }
+        if !self.arch_satisfies(alt, candidate) {
+            return None;
+        }
+        if candidate == alt.package_num && !self.version_satisfies(alt, version) {
+            return None;
+        }
+        let removals = self.conflicts_with_solution(state, candidate, version).ok()?;
+        if removals.is_empty() && !self.installed_debvers.contains_key(&candidate) {
+            // installing a new package can't unsatisfy anything
+            return Some(removals);
+        }
+        // upgrading an installed package or removing a replaced one might break an earlier goal which relied on it
+        let version_after = |pn: i32| {
+            if pn == candidate { Some(version) }
+            else if removals.contains(&pn) { None }
+            else { self.version_in_solution(state, pn) }
+        };
+        let moot_after = |i: usize, needed_by: i32| i < state.installed_goals && (needed_by == candidate || removals.contains(&needed_by));
+        let still_satisfied = state.goals[..goal].iter().enumerate().all(|(i, (needed_by, dependency, soft))| {
+            *soft || state.goal_is_moot(i) || moot_after(i, *needed_by) || self.goal_satisfied_with(dependency, &version_after)
+        });
+        if still_satisfied { Some(removals) } else { None }
+    }
+
+    /// Checks whether installing package_num at version clashes with the solution so far, either through its own
+    /// Conflicts/Breaks or through those of an installed or chosen package. Relations against a virtual package apply
+    /// to its providers. A conflict with an installed package that package_num also Replaces is fine: the installed
+    /// package gets removed. Every package has the relations of its candidate at the version it would have.
+    /// Returns the packages to remove.
+    fn conflicts_with_solution(&self, state: &SolverState, package_num: i32, version: &DebianVersionNum) -> Result<Vec<i32>, String> {
+        let name = self.get_package_name(package_num);
+        let candidate = self.candidate_for(package_num, version);
+        let mut removals = vec![];
+        for (kind, rels) in [("conflicts with", candidate.and_then(|c| c.conflicts.as_ref())), ("breaks", candidate.and_then(|c| c.breaks.as_ref()))] {
+            for rel in rels.into_iter().flatten() {
+                let mut targets : Vec<(i32, &DebianVersionNum)> = vec![];
+                if let Some(v) = self.version_in_solution(state, rel.package_num) {
+                    if self.version_satisfies(rel, v) {
+                        targets.push((rel.package_num, v));
+                    }
+                }
+                for provider in self.providers_satisfying(rel) {
+                    if let Some(v) = self.version_in_solution(state, provider) {
+                        targets.push((provider, v));
+                    }
+                }
+                for (target, target_version) in targets {
+                    if target == package_num || removals.contains(&target) {
+                        continue
+                    }
+                    if kind == "conflicts with" && !state.selected.contains_key(&target) && self.package_replaces(package_num, target, target_version) {
+                        removals.push(target);
+                    } else {
+                        return Err(format!("{} {} {} {}", name, kind, self.get_package_name(target), target_version));
+                    }
+                }
+            }
+        }
+        // relations against package_num itself, or against something it provides
+        let mut objections : Vec<&Objection> = state.conflicts_index.get(&package_num).into_iter().flatten()
+            .filter(|(_, _, _, rel)| self.version_satisfies(rel, version)).collect();
+        for provided in candidate.and_then(|c| c.provides.as_ref()).into_iter().flatten() {
+            objections.extend(state.conflicts_index.get(&provided.package_num).into_iter().flatten()
+                .filter(|(_, _, _, rel)| self.provide_satisfies(rel, provided)));
+        }
+        for (other, other_version, kind, rel) in objections {
+            if *other == package_num || removals.contains(other) {
                 continue
             }
-            else {
-                if let Some(package_num) = self.handle_dependency_comparison(dependency_group) {
-                    if !dependencies_to_add.contains(&package_num)  {
-                        dependencies_to_add.push(package_num)
-                     }
-                }
+            // only if other is there, at the version whose relation this is
+            let objects = self.version_in_solution(state, *other)
+                .is_some_and(|v| self.candidate_for(*other, v).is_some_and(|c| c.version == **other_version));
+            if objects {
+                return Err(format!("{} {} {}", self.get_package_name(*other), kind, self.rel2str(rel)));
             }
         }
-        let mut seen_deps: Vec<i32> = Vec::new();
-        loop {
-            let mut new_dependencies:Vec<i32> = Vec::new();
-            for package_num in &dependencies_to_add {
-                let deps = &*self.dependencies.get(&package_num).unwrap();
-                for dep in deps {
-                    if let Some(package_num) = self.handle_dependency_comparison(dep) {
-                        if !&seen_deps.contains(&package_num) {
-                            seen_deps.push(package_num);
-                            new_dependencies.push(package_num)
-                        }
+        Ok(removals)
+    }
+
+    /// Indexes the Conflicts and Breaks relations of every candidate by the package they're against, so that the
+    /// solver can check whether anything already installed or chosen objects to a package it wants to add.
+    fn conflicts_index(&self) -> HashMap<i32, Vec<Objection<'_>>> {
+        let mut index : HashMap<i32, Vec<Objection>> = HashMap::new();
+        for (package_num, candidates) in &self.candidates {
+            for c in candidates {
+                for (kind, rels) in [("conflicts with", &c.conflicts), ("breaks", &c.breaks)] {
+                    for rel in rels.iter().flatten() {
+                        index.entry(rel.package_num).or_default().push((*package_num, &c.version, kind, rel));
                     }
                 }
             }
+        }
+        index
+    }
 
-            if new_dependencies.is_empty() {
-                break;
-            }
-            for i in new_dependencies {
-                if !dependencies_to_add.contains(&i) {
-                    dependencies_to_add.push(i);
+    /// Sorts the alternatives of a dependency into the order in which the solver should try them, as triples of
+    /// (package to install, alternative it satisfies, version). Real packages come first, each with its candidates in
+    /// order of preference, then packages providing them, at their available versions.
+    fn order_alternatives<'a>(&'a self, dependency: &'a Dependency) -> Vec<(i32, &'a RelVersionedPackageNum, &'a DebianVersionNum)> {
+        let (mut installed, mut uninstalled) : (Vec<&RelVersionedPackageNum>, Vec<&RelVersionedPackageNum>) =
+            dependency.iter().partition(|alt| self.installed_debvers.contains_key(&alt.package_num));
+        installed.sort_by(|a, b| self.installed_debvers.get(&b.package_num).cmp(&self.installed_debvers.get(&a.package_num)));
+        let preference = |alt: &RelVersionedPackageNum| (self.preferred_priority(alt.package_num), self.available_debvers.get(&alt.package_num));
+        uninstalled.sort_by(|a, b| preference(b).cmp(&preference(a)));
+        installed.extend(uninstalled);
+        let mut candidates : Vec<(i32, &RelVersionedPackageNum, &DebianVersionNum)> = installed.iter()
+            .flat_map(|alt| self.candidate_versions(alt.package_num).into_iter().map(move |v| (alt.package_num, *alt, v))).collect();
+        for alt in dependency {
+            candidates.extend(self.providers_satisfying(alt).into_iter().filter_map(|p| self.available_debvers.get(&p).map(|v| (p, alt, v))));
+        }
+        candidates
+    }
+
+    /// Returns an installed package which upgrading (or downgrading) package_num to version alone would break.
+    fn breaks_installed_at(&self, package_num: i32, version: &DebianVersionNum) -> Option<i32> {
+        self.plan_breaks_installed(&InstallPlan { install : vec![package_num], versions : HashMap::from([(package_num, version.clone())]),
+                                                  remove : vec![], cycles : vec![] })
+    }
+
+    fn describe_goal(&self, package_num: i32, dependency: &Dependency) -> String {
+        format!("\"{}\" (required by {})", self.dep2str(dependency), self.get_package_name(package_num))
+    }
+
+    /// Explains, alternative by alternative, why the given goal can't be satisfied.
+    fn explain_failure(&self, package_num: i32, dependency: &Dependency) -> String {
+        let reasons = dependency.iter().map(|alt| {
+            let name = self.get_package_name(alt.package_num);
+            match (self.available_debvers.get(&alt.package_num), self.installed_debvers.get(&alt.package_num)) {
+                (None, None) if self.providers.contains_key(&alt.package_num) => {
+                    let providers = self.providers_satisfying(alt);
+                    if providers.is_empty() {
+                        format!("{} is a virtual package, but none of its providers provide the right version", name)
+                    } else {
+                        format!("{} is a virtual package, and none of its providers ({}) can be installed", name,
+                                providers.iter().map(|p| self.get_package_name(*p)).format(", "))
+                    }
+                },
+                (None, None) => format!("{} is not available", name),
+                _ if !self.arch_satisfies(alt, alt.package_num) =>
+                    format!("{} is for architecture {}", name, self.architectures.get(&alt.package_num).map_or("?", |a| a.as_str())),
+                (None, Some(iv)) => format!("{} is installed at version {} and no other version is available", name, iv),
+                (Some(_), _) if !self.candidate_versions(alt.package_num).iter().any(|v| self.version_satisfies(alt, v)) =>
+                    format!("{} is only available at version {}", name, self.candidate_versions(alt.package_num).iter().format(", ")),
+                (Some(av), iv) => match iv.and_then(|_| self.breaks_installed_at(alt.package_num, av)) {
+                    Some(broken) => format!("{} {} would break installed {}", name, av, self.get_package_name(broken)),
+                    None => format!("{} {} conflicts with the versions or packages required by other dependencies", name, av)
                 }
             }
+        }).format("; ");
+        let wrong_versions = if self.dep_is_satisfied(dependency).is_none() { self.dep_satisfied_by_wrong_version(dependency) } else { vec![] };
+        if wrong_versions.is_empty() {
+            format!("cannot satisfy {}: {}", self.describe_goal(package_num, dependency), reasons)
+        } else {
+            format!("cannot satisfy {}: {} (installed at the wrong version: {})",
+                    self.describe_goal(package_num, dependency), reasons, wrong_versions.iter().format(", "))
         }
+    }
+}
+
+/// Upper bound on how many choices the solver undoes before giving up on a package.
+const MAX_BACKTRACKS : usize = 100000;
 
-        return dependencies_to_add;
+/// What the solver proposes: packages to install (or upgrade), in an order in which they can be unpacked and
+/// configured, the version each one is installed at, installed packages they replace, and the dependency cycles among
+/// the packages to install.
+pub struct InstallPlan {
+    pub install : Vec<i32>,
+    pub versions : HashMap<i32, DebianVersionNum>,
+    pub remove : Vec<i32>,
+    pub cycles : Vec<Vec<i32>>
+}
+
+/// What the hard goals so far require of a package: the versions they allow, and whose goals they are.
+type Requirement = (VersionConstraint, Vec<i32>);
+
+/// A Conflicts or Breaks relation, indexed by the package it's against: which package (at which version) has it.
+type Objection<'a> = (i32, &'a DebianVersionNum, &'static str, &'a RelVersionedPackageNum);
+
+/// A partial solution: which packages we've decided to install and at which version, which installed packages
+/// they replace, plus the work-list of dependencies (goals) that still need to be checked.
+struct SolverState<'a> {
+    selected : HashMap<i32, &'a DebianVersionNum>,
+    order : Vec<i32>, // selected packages, in the order they were chosen
+    removed : HashSet<i32>,
+    removal_order : Vec<i32>,
+    goals : Vec<(i32, &'a Dependency, bool)>, // (package which needs it, dependency, whether it's only a Recommends)
+    installed_goals : usize, // goals[..installed_goals] are installed packages' dependencies
+    next_goal : usize,
+    install_recommends : bool,
+    conflicts_index : HashMap<i32, Vec<Objection<'a>>>,
+    required : HashMap<i32, Requirement>,
+    required_log : Vec<(i32, Option<Requirement>)> // previous entries of required, to undo changes
+}
+
+/// Enough information to undo a choice and resume with its next alternative.
+struct ChoicePoint {
+    goal : usize,
+    next_alternative : usize,
+    selected_len : usize,
+    removed_len : usize,
+    goals_len : usize,
+    required_len : usize
+}
+
+impl<'a> SolverState<'a> {
+    fn new(conflicts_index: HashMap<i32, Vec<Objection<'a>>>, install_recommends: bool) -> SolverState<'a> {
+        SolverState { selected : HashMap::new(), order : vec![], removed : HashSet::new(), removal_order : vec![],
+                      goals : vec![], installed_goals : 0, next_goal : 0, install_recommends, conflicts_index,
+                      required : HashMap::new(), required_log : vec![] }
+    }
+
+    /// An installed package's dependency no longer counts once the solver has chosen that package (its chosen
+    /// version brings goals of its own) or removes it.
+    fn goal_is_moot(&self, goal: usize) -> bool {
+        let package_num = self.goals[goal].0;
+        goal < self.installed_goals && (self.selected.contains_key(&package_num) || self.removed.contains(&package_num))
     }
 
-    pub fn handle_dependency_comparison(&self, dependency_group:&Vec<RelVersionedPackageNum>) -> Option<i32> {
-        if let Some(pkg_satisfying) = self.dep_is_satisfied(dependency_group) {
-            return None
+    fn select(&mut self, package_num: i32, version: &'a DebianVersionNum, removals: Vec<i32>) {
+        self.selected.insert(package_num, version);
+        self.order.push(package_num);
+        for r in removals {
+            if self.removed.insert(r) {
+                self.removal_order.push(r);
+            }
         }
-        else {
-            let wrong_versions = self.dep_satisfied_by_wrong_version(dependency_group);
-            if wrong_versions.len() == 0 {
-                let mut versions = vec!["0".parse::<debversion::DebianVersionNum>().unwrap()];
-                let mut max_package:i32 = 0;
-                for package in dependency_group {
-                    let package_num = package.package_num;
-                    if let Some(vers) = &package.rel_version {
-                        let v = vers.1.parse::<debversion::DebianVersionNum>().unwrap();
-                        // compare current version with max version
-                        let greater_than = debversion::cmp_debversion_with_op(&debversion::VersionRelation::StrictlyGreater, &v, &versions[versions.len() - 1]);
-                        if greater_than {
-                            versions.push(v);
-                            max_package = package_num;
-                        }
+    }
 
+    fn undo(&mut self, choice_point: &ChoicePoint) {
+        for package_num in self.order.drain(choice_point.selected_len..) {
+            self.selected.remove(&package_num);
+        }
+        for package_num in self.removal_order.drain(choice_point.removed_len..) {
+            self.removed.remove(&package_num);
+        }
+        self.goals.truncate(choice_point.goals_len);
+        for (package_num, previous) in self.required_log.drain(choice_point.required_len..).rev() {
+            match previous {
+                None => self.required.remove(&package_num),
+                Some(p) => self.required.insert(package_num, p)
+            };
+        }
+        self.next_goal = choice_point.goal;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    #[test]
+    fn backtracks_out_of_an_alternative_whose_dependencies_break_the_solution() {
+        // c needs libx 2.0, but b needs the installed libx 1.0, so the solver has to go back and take d
+        let p = Packages::load_fixture("solver/backtrack");
+        let plan = p.compute_how_to_install(&[p.get_package_num("a").unwrap()], false).unwrap();
+        assert_eq!(p.names(&plan.install), ["b", "d"]);
+        assert!(plan.remove.is_empty());
+    }
+
+    #[test]
+    fn explains_why_a_dependency_is_unsatisfiable() {
+        let p = Packages::load_fixture("solver/unsatisfiable");
+        let why = p.compute_how_to_install(&[p.get_package_num("a").unwrap()], false).err().unwrap().to_string();
+        assert!(why.starts_with("cannot satisfy \"b (>= 3.0) | missing\" (required by a)"), "{}", why);
+        assert!(why.contains("b is only available at version 2.0"), "{}", why);
+        assert!(why.contains("missing is not available"), "{}", why);
+    }
+
+    #[test]
+    fn reports_conflicting_version_requirements() {
+        let p = Packages::load_fixture("solver/requirements");
+        let why = p.compute_how_to_install(&[p.get_package_num("a").unwrap()], false).err().unwrap().to_string();
+        assert!(why.contains("conflicting requirements on b"), "{}", why);
+    }
+
+    #[test]
+    fn keeps_installed_packages_working() {
+        // upgrading libx to 2.0 would break the installed old, which needs libx << 2.0
+        let p = Packages::load_fixture("solver/installed");
+        let plan = p.compute_how_to_install(&[p.get_package_num("n").unwrap()], false).unwrap();
+        assert_eq!(p.names(&plan.install), ["liby"]);
+        let why = p.compute_how_to_install(&[p.get_package_num("m").unwrap()], false).err().unwrap().to_string();
+        assert!(why.contains("libx 2.0 would break installed old"), "{}", why);
+    }
+
+    #[test]
+    fn removes_a_conflicting_installed_package_that_it_replaces() {
+        let p = Packages::load_fixture("solver/replaces");
+        let plan = p.compute_how_to_install(&[p.get_package_num("postfix").unwrap()], false).unwrap();
+        assert!(plan.install.is_empty());
+        assert_eq!(p.names(&plan.remove), ["exim4"]);
+    }
+
+    #[test]
+    fn refuses_a_conflict_with_an_installed_package_it_does_not_replace() {
+        // mailsys only replaces exim4 << 4.0, and 4.94 is installed
+        let p = Packages::load_fixture("solver/replaces");
+        let why = p.compute_how_to_install(&[p.get_package_num("mailsys").unwrap()], false).err().unwrap().to_string();
+        assert_eq!(why, "mailsys conflicts with exim4 4.94");
+    }
+
+    #[test]
+    fn skips_a_recommends_it_cannot_satisfy() {
+        let p = Packages::load_fixture("solver/recommends");
+        let fancy = p.get_package_num("fancy").unwrap();
+        let plan = p.compute_how_to_install(&[fancy], true).unwrap();
+        assert_eq!(p.names(&plan.install), ["dpkg", "libb", "libextra"]);
+        let plan = p.compute_how_to_install(&[fancy], false).unwrap();
+        assert_eq!(p.names(&plan.install), ["dpkg", "libb"]);
+    }
+
+    #[test]
+    fn fails_on_a_pre_depends_it_cannot_satisfy() {
+        let p = Packages::load_fixture("solver/recommends");
+        let why = p.compute_how_to_install(&[p.get_package_num("strict").unwrap()], true).err().unwrap().to_string();
+        assert_eq!(why, "no version of dpkg satisfies >= 2.0 (for strict); possible versions: 1.21");
+    }
+
+    /// The original transitive_dep_solution, which rescans the whole solution so far on every round and looks packages
+    /// up in it with Vec::contains, so it is quadratic; the baseline for the benchmark below.
+    fn transitive_dep_solution_rescanning(p: &Packages, package_num: i32) -> Vec<i32> {
+        let mut dependency_set : Vec<i32> = vec![];
+        for dep in p.dependencies.get(&package_num).into_iter().flatten() {
+            if !dependency_set.contains(&dep[0].package_num) {
+                dependency_set.push(dep[0].package_num);
+            }
+        }
+
+        loop {
+            let mut new_dependencies = Vec::new();
+            for package_num in &dependency_set {
+                for dep in p.dependencies.get(package_num).into_iter().flatten() {
+                    let pkg_num = dep[0].package_num;
+                    if !dependency_set.contains(&pkg_num) && !new_dependencies.contains(&pkg_num) {
+                        new_dependencies.push(pkg_num);
                     }
                 }
-                return Some(max_package)
-                // check versions for all deps in the group
-            }
-            else {
-                let min_version = "0";
-                let v1 = min_version.parse::<debversion::DebianVersionNum>().unwrap();
-                let mut versions = &v1;
-                let mut max_package:i32 = 0;
-                for package_name in wrong_versions {
-                    let package_num = *self.get_package_num(package_name);
-                    if let Some(v) = self.get_installed_debver(package_name) {
-                        let greater_than = debversion::cmp_debversion_with_op(&debversion::VersionRelation::StrictlyGreater, &v, &versions);
-                        if greater_than {
-                            versions = v;
-                            max_package = package_num;
-                        }
-                    }
-                }
-                return Some(max_package);
             }
+
+            if new_dependencies.is_empty() {
+                break;
+            }
+            dependency_set.extend(new_dependencies);
         }
+        dependency_set
+    }
 
+    /// Times transitive_dep_solution against the rescanning version on a Debian Packages file, computing the closure
+    /// of every 100th package (all of them would take the rescanning version hours), and checks that both find the same
+    /// packages. The file is $RPKG_BENCH_PACKAGES, or else the default one under data/; if it isn't there, there's
+    /// nothing to measure. Slow, so it only runs when asked for:
+    /// RPKG_BENCH_PACKAGES=<file> cargo test --release -- --ignored --nocapture transitive_dep_solution
+    #[test]
+    #[ignore]
+    fn transitive_dep_solution_is_faster_than_rescanning() {
+        let filename = std::env::var("RPKG_BENCH_PACKAGES")
+            .unwrap_or_else(|_| String::from("data/mirror.csclub.uwaterloo.ca_debian_dists_sid_main_binary-amd64_Packages"));
+        if !std::path::Path::new(&filename).exists() {
+            println!("skipping: no Packages file at {}", filename);
+            return;
+        }
+        let mut p = Packages::new();
+        p.parse_packages(&filename).unwrap();
+        let mut packages : Vec<i32> = p.package_num_to_name.keys().copied().collect();
+        packages.sort_unstable();
+        let packages : Vec<i32> = packages.into_iter().step_by(100).collect();
+
+        let start = Instant::now();
+        let worklist : Vec<Vec<i32>> = packages.iter().map(|pn| p.transitive_dep_solution(&[*pn])).collect();
+        let worklist_time = start.elapsed();
+        let start = Instant::now();
+        let rescanning : Vec<Vec<i32>> = packages.iter().map(|pn| transitive_dep_solution_rescanning(&p, *pn)).collect();
+        let rescanning_time = start.elapsed();
+
+        let closure_size : usize = worklist.iter().map(|closure| closure.len()).sum();
+        println!("transitive-dep-solution for {} packages ({} dependencies in all):", packages.len(), closure_size);
+        println!("- work-list: {:?}", worklist_time);
+        println!("- rescanning: {:?} ({:.1}x)", rescanning_time, rescanning_time.as_secs_f64() / worklist_time.as_secs_f64().max(1e-9));
+        for (pn, (a, b)) in packages.iter().zip(worklist.iter().zip(rescanning.iter())) {
+            assert_eq!(a.iter().collect::<HashSet<_>>(), b.iter().collect::<HashSet<_>>(), "closures of {} differ", p.get_package_name(*pn));
+        }
     }
 }
@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use rpkg::debversion::DebianVersionNum;
+use rpkg::error::RpkgError;
+
+use crate::Packages;
+use crate::packages::solvers::InstallPlan;
+
+/// The state of the packages a transaction changed, from before it ran, so that rollback can restore it:
+/// (package, installed version or None, whether it was marked Auto-Installed).
+pub struct Transaction {
+    previous : Vec<(i32, Option<DebianVersionNum>, bool)>
+}
+
+impl Packages {
+    /// Installs package_name (marked as manually installed) into installed_debvers, together with whatever
+    /// compute_how_to_install says it needs (marked Auto-Installed, unless already installed) and removing
+    /// whatever it replaces; unless that would break another installed package (see plan_breaks_installed).
+    /// Only changes the in-memory state; use save-installed to write it out.
+    pub fn install(&mut self, package_name: &str, install_recommends: bool) -> Result<(), RpkgError> {
+        let package_num = self.get_package_num(package_name)?;
+        let mut plan = match self.compute_how_to_install(&[package_num], install_recommends) {
+            Err(RpkgError::Unsatisfiable(why)) => {
+                println!("{:?} cannot be installed: {}", package_name, why);
+                return Ok(());
+            }
+            other => other?
+        };
+        plan.install.push(package_num);
+        if let Some(broken) = self.plan_breaks_installed(&plan) {
+            println!("{:?} can only be installed by breaking installed {}", package_name, self.get_package_name(broken));
+            return Ok(());
+        }
+        self.apply(&plan);
+        self.auto_installed.remove(&package_num);
+        Ok(())
+    }
+
+    /// Removes package_name from installed_debvers, along with the installed packages that would break
+    /// (see compute_how_to_remove).
+    pub fn remove(&mut self, package_name: &str) -> Result<(), RpkgError> {
+        let removed = self.compute_how_to_remove(package_name)?;
+        self.apply(&InstallPlan { install : vec![], versions : HashMap::new(), remove : removed, cycles : vec![] });
+        Ok(())
+    }
+
+    /// Carries out plan on installed_debvers, prints what changed, and records how to undo it.
+    /// Newly installed packages are marked Auto-Installed.
+    fn apply(&mut self, plan: &InstallPlan) {
+        let mut transaction = Transaction { previous : vec![] };
+        for package_num in plan.remove.iter().chain(plan.install.iter()) {
+            transaction.previous.push((*package_num, self.installed_debvers.get(package_num).cloned(), self.auto_installed.contains(package_num)));
+        }
+        for package_num in &plan.remove {
+            let old = self.installed_debvers.remove(package_num).unwrap();
+            self.auto_installed.remove(package_num);
+            println!("- remove {} {}", self.get_package_name(*package_num), old);
+        }
+        for package_num in &plan.install {
+            let new = self.planned_version(plan, *package_num).unwrap().clone();
+            match self.installed_debvers.insert(*package_num, new.clone()) {
+                None => {
+                    self.auto_installed.insert(*package_num);
+                    println!("- install {} {}", self.get_package_name(*package_num), new);
+                }
+                Some(old) if old == new => println!("- keep {} {}", self.get_package_name(*package_num), new),
+                Some(old) => println!("- upgrade {} {} -> {}", self.get_package_name(*package_num), old, new)
+            }
+        }
+        self.transactions.push(transaction);
+    }
+
+    /// Undoes the most recent install or remove.
+    pub fn rollback(&mut self) {
+        let transaction = match self.transactions.pop() {
+            None => { println!("nothing to roll back"); return }
+            Some(t) => t
+        };
+        // restore in reverse, so that a package changed twice ends up as it was first
+        for (package_num, version, auto) in transaction.previous.into_iter().rev() {
+            let name = self.get_package_name(package_num).to_string();
+            match version {
+                None => {
+                    self.installed_debvers.remove(&package_num);
+                    println!("- uninstall {}", name);
+                }
+                Some(v) => {
+                    println!("- restore {} {}", name, v);
+                    self.installed_debvers.insert(package_num, v);
+                }
+            }
+            if auto { self.auto_installed.insert(package_num); } else { self.auto_installed.remove(&package_num); }
+        }
+    }
+
+    /// Writes the installed packages to fname, sorted by name, in the stanza format that parse_installed reads.
+    pub fn save_installed(&self, fname: &str) -> Result<(), RpkgError> {
+        let mut installed : Vec<(&str, i32)> = self.installed_debvers.keys().map(|pn| (self.get_package_name(*pn), *pn)).collect();
+        installed.sort();
+        let mut out = String::new();
+        for (name, package_num) in installed {
+            writeln!(out, "Package: {}", name).unwrap();
+            writeln!(out, "Status: install ok installed").unwrap();
+            if let Some(arch) = self.architectures.get(&package_num) {
+                writeln!(out, "Architecture: {}", arch).unwrap();
+            }
+            writeln!(out, "Version: {}", self.installed_debvers.get(&package_num).unwrap()).unwrap();
+            if self.auto_installed.contains(&package_num) {
+                writeln!(out, "Auto-Installed: 1").unwrap();
+            }
+            out.push('\n');
+        }
+        std::fs::write(fname, out).map_err(|e| RpkgError::Io { file : fname.to_string(), source : e })?;
+        println!("Packages saved: {}", self.installed_debvers.len());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn install_refuses_to_break_an_installed_package() {
+        // n needs libx 2.0, but the installed old needs libx << 2.0
+        let mut p = Packages::load_fixture("transactions/breaks");
+        let before = p.installed_debvers.clone();
+        p.install("n", false).unwrap();
+        assert!(p.installed_debvers == before);
+        assert!(p.transactions.is_empty());
+    }
+}
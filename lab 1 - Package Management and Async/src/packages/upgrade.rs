@@ -0,0 +1,131 @@
+use std::collections::{HashMap, HashSet};
+
+use rpkg::debversion::DebianVersionNum;
+
+use crate::Packages;
+use crate::packages::DependencyKind;
+use crate::packages::preferences::{DOWNGRADE_PRIORITY, INSTALLED_PRIORITY};
+use crate::packages::solvers::InstallPlan;
+
+/// What an upgrade planner proposes, plus the upgradable packages it had to leave alone and why.
+pub struct UpgradePlan {
+    pub plan : InstallPlan,
+    pub kept_back : Vec<(i32, String)>
+}
+
+impl Packages {
+    /// Returns the installed packages whose available version should replace the installed one, sorted by name.
+    /// As with apt pinning, a newer version needs at least the priority of an installed version (INSTALLED_PRIORITY)
+    /// and an older one at least DOWNGRADE_PRIORITY.
+    pub fn upgradable(&self) -> Vec<i32> {
+        let mut upgradable : Vec<i32> = self.installed_debvers.iter()
+            .filter(|(pn, iv)| match (self.available_debvers.get(pn), self.preferred_priority(**pn)) {
+                (Some(av), Some(priority)) => (av > iv && priority >= INSTALLED_PRIORITY) || (av < iv && priority >= DOWNGRADE_PRIORITY),
+                _ => false
+            })
+            .map(|(pn, _)| *pn).collect();
+        upgradable.sort_by(|a, b| self.get_package_name(*a).cmp(self.get_package_name(*b)));
+        upgradable
+    }
+
+    pub fn print_upgradable(&self) {
+        for package_num in self.upgradable() {
+            println!("- {} {} -> {}", self.get_package_name(package_num), self.installed_debvers.get(&package_num).unwrap(),
+                     self.available_debvers.get(&package_num).unwrap());
+        }
+    }
+
+    /// Plans upgrading as many upgradable packages as possible, one at a time in name order: each is added to the
+    /// packages being upgraded if the solver finds a way to install all of them together that doesn't break any
+    /// installed package. Like apt, upgrade (allow_changes = false) only accepts solutions that upgrade installed
+    /// packages, while dist-upgrade (allow_changes = true) may also install new packages and remove replaced ones.
+    pub fn compute_upgrade(&self, allow_changes: bool) -> UpgradePlan {
+        let mut roots : Vec<i32> = vec![];
+        let mut plan = InstallPlan { install : vec![], versions : HashMap::new(), remove : vec![], cycles : vec![] };
+        let mut kept_back = vec![];
+        for package_num in self.upgradable() {
+            if plan.install.contains(&package_num) {
+                // already upgraded to satisfy some other upgrade
+                continue
+            }
+            roots.push(package_num);
+            let candidate = self.solve(&roots, false).and_then(|candidate| {
+                if !allow_changes {
+                    if let Some(new) = candidate.install.iter().find(|pn| !self.installed_debvers.contains_key(pn)) {
+                        return Err(format!("needs new package {}", self.get_package_name(*new)));
+                    }
+                    if let Some(removed) = candidate.remove.first() {
+                        return Err(format!("needs {} removed", self.get_package_name(*removed)));
+                    }
+                }
+                match self.plan_breaks_installed(&candidate) {
+                    Some(broken) => Err(format!("would break installed {}", self.get_package_name(broken))),
+                    None => Ok(candidate)
+                }
+            });
+            match candidate {
+                Ok(candidate) => plan = candidate,
+                Err(why) => {
+                    roots.pop();
+                    kept_back.push((package_num, why));
+                }
+            }
+        }
+        UpgradePlan { plan, kept_back }
+    }
+
+    /// Returns an installed package, outside the plan, with a Pre-Depends or Depends that is satisfied now but
+    /// wouldn't be after carrying out the plan, if there is one.
+    pub fn plan_breaks_installed(&self, plan: &InstallPlan) -> Option<i32> {
+        let before = |pn: i32| self.installed_debvers.get(&pn);
+        let after = |pn: i32| -> Option<&DebianVersionNum> {
+            if plan.remove.contains(&pn) { None }
+            else if plan.install.contains(&pn) { self.planned_version(plan, pn) }
+            else { self.installed_debvers.get(&pn) }
+        };
+        let changed : HashSet<i32> = plan.install.iter().chain(plan.remove.iter()).copied().collect();
+        let mut affected : Vec<i32> = changed.iter().flat_map(|pn| self.direct_rdepends(*pn, true)).map(|(rdep, _, _)| rdep)
+            .filter(|rdep| !changed.contains(rdep)).collect();
+        affected.sort_unstable();
+        affected.dedup();
+        affected.into_iter().find(|rdep| {
+            [DependencyKind::PreDepends, DependencyKind::Depends].iter()
+                .flat_map(|kind| self.dependencies_of_kind(*kind, *rdep).into_iter().flatten())
+                .filter(|dep| self.dependency_applies(dep))
+                .any(|dep| self.goal_satisfied_with(dep, &before) && !self.goal_satisfied_with(dep, &after))
+        })
+    }
+
+    /// Prints the transaction an upgrade planner proposes: version transitions for upgrades, new packages,
+    /// removals, and the packages kept back, followed by an apt-style summary line.
+    pub fn print_upgrade(&self, allow_changes: bool) {
+        let UpgradePlan { plan, kept_back } = self.compute_upgrade(allow_changes);
+        let (upgrades, new) : (Vec<i32>, Vec<i32>) = plan.install.iter().partition(|pn| self.installed_debvers.contains_key(pn));
+        let version = |pn: &i32| self.planned_version(&plan, *pn).unwrap();
+        if !upgrades.is_empty() {
+            println!("Upgrade:");
+            for pn in &upgrades {
+                println!("- {} {} -> {}", self.get_package_name(*pn), self.installed_debvers.get(pn).unwrap(), version(pn));
+            }
+        }
+        if !new.is_empty() {
+            println!("Install:");
+            for pn in &new {
+                println!("- {} {}", self.get_package_name(*pn), version(pn));
+            }
+        }
+        if !plan.remove.is_empty() {
+            println!("Remove:");
+            for pn in &plan.remove {
+                println!("- {} {}", self.get_package_name(*pn), self.installed_debvers.get(pn).unwrap());
+            }
+        }
+        if !kept_back.is_empty() {
+            println!("Kept back:");
+            for (pn, why) in &kept_back {
+                println!("- {} {} -> {}: {}", self.get_package_name(*pn), self.installed_debvers.get(pn).unwrap(), version(pn), why);
+            }
+        }
+        println!("{} upgraded, {} newly installed, {} to remove and {} not upgraded.", upgrades.len(), new.len(), plan.remove.len(), kept_back.len());
+    }
+}
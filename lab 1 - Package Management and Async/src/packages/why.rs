@@ -0,0 +1,164 @@
+use std::collections::{HashMap, VecDeque};
+
+use itertools::Itertools;
+
+use rpkg::error::RpkgError;
+
+use crate::Packages;
+use crate::packages::{Dependency, DependencyKind};
+
+/// One link in a chain of dependencies: the package, which field, the dependency, and the provider if it goes through a
+/// virtual package.
+type PathStep<'a> = (i32, DependencyKind, &'a Dependency, Option<i32>);
+
+impl Packages {
+    /// Finds a shortest chain of Pre-Depends and Depends leading from package_num to target, trying every alternative
+    /// and every provider of a virtual package.
+    pub fn dependency_path(&self, package_num: i32, target: i32) -> Option<Vec<PathStep<'_>>> {
+        let mut parent : HashMap<i32, PathStep> = HashMap::new();
+        let mut worklist = VecDeque::from([package_num]);
+        while let Some(pn) = worklist.pop_front() {
+            if pn == target {
+                let mut path = vec![];
+                let mut current = target;
+                while current != package_num {
+                    let step = parent[&current];
+                    current = step.0;
+                    path.push(step);
+                }
+                path.reverse();
+                return Some(path);
+            }
+            for kind in [DependencyKind::PreDepends, DependencyKind::Depends].iter() {
+                for dep in self.dependencies_of_kind(*kind, pn).into_iter().flatten().filter(|dep| self.dependency_applies(dep)) {
+                    for alt in dep.iter().filter(|alt| self.alternative_applies(alt)) {
+                        let providers = self.providers.get(&alt.package_num).into_iter().flatten().map(|p| (p.package_num, Some(p.package_num)));
+                        for (next, via) in std::iter::once((alt.package_num, None)).chain(providers) {
+                            if next != package_num && !parent.contains_key(&next) {
+                                parent.insert(next, (pn, *kind, dep, via));
+                                worklist.push_back(next);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Prints why package_name needs dependency_name: the shortest chain of dependencies from one to the other.
+    pub fn print_why(&self, package_name: &str, dependency_name: &str) -> Result<(), RpkgError> {
+        let package_num = self.get_package_num(package_name)?;
+        let target = self.get_package_num(dependency_name)?;
+        match self.dependency_path(package_num, target).filter(|path| !path.is_empty()) {
+            None => println!("{:?} does not depend on {:?}", package_name, dependency_name),
+            Some(path) => {
+                println!("{:?} needs {:?}:", package_name, dependency_name);
+                for (pn, kind, dep, via) in path {
+                    match via {
+                        None => println!("- {} {} {:?}", self.get_package_name(pn), kind.verb(), self.dep2str(dep)),
+                        Some(provider) => println!("- {} {} {:?}, provided by {}", self.get_package_name(pn), kind.verb(), self.dep2str(dep), self.get_package_name(provider))
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Explains why package_name can't simply be installed: for each unsatisfied Pre-Depends or Depends, which installed
+    /// package is at the wrong version and, if upgrading it would help, which installed packages' dependencies forbid
+    /// the upgrade; or why none of the alternatives is available. Finishes with the solver's verdict, which also says whether
+    /// its plan would break an installed package.
+    pub fn print_why_not(&self, package_name: &str) -> Result<(), RpkgError> {
+        let package_num = self.get_package_num(package_name)?;
+        println!("Package {}:", package_name);
+        let mut all_satisfied = true;
+        for kind in [DependencyKind::PreDepends, DependencyKind::Depends].iter() {
+            for dep in self.dependencies_of_kind(*kind, package_num).into_iter().flatten().filter(|dep| self.dependency_applies(dep)) {
+                if self.dep_is_satisfied(dep).is_some() {
+                    continue
+                }
+                all_satisfied = false;
+                println!("- dependency {:?} is not satisfied", self.dep2str(dep));
+                let wrong_versions = self.dep_satisfied_by_wrong_version(dep);
+                for name in &wrong_versions {
+                    self.explain_wrong_version(dep, self.get_package_num(name)?);
+                }
+                if wrong_versions.is_empty() {
+                    self.explain_not_installed(dep);
+                }
+            }
+        }
+        if all_satisfied {
+            println!("-> all dependencies are satisfied");
+        }
+        match self.compute_how_to_install(&[package_num], false) {
+            Ok(plan) => match self.plan_breaks_installed(&plan) {
+                Some(broken) => println!("{:?} can only be installed by breaking installed {}", package_name, self.get_package_name(broken)),
+                None => println!("{:?} can be installed (see how-to-install)", package_name)
+            },
+            Err(RpkgError::Unsatisfiable(why)) => println!("{:?} cannot be installed: {}", package_name, why),
+            Err(e) => return Err(e)
+        }
+        Ok(())
+    }
+
+    /// Explains why installed package_num doesn't satisfy dep, and whether upgrading it to the most preferred version
+    /// that satisfies it would break another installed package.
+    fn explain_wrong_version(&self, dep: &Dependency, package_num: i32) {
+        let name = self.get_package_name(package_num);
+        let installed = &self.installed_debvers[&package_num];
+        let alt = match dep.iter().find(|alt| alt.package_num == package_num) {
+            Some(alt) => alt,
+            None => {
+                let virtuals = dep.iter().filter(|alt| self.providers_not_satisfying(alt).contains(&package_num)).map(|alt| self.rel2str(alt));
+                for virt in virtuals {
+                    println!("-> installed {} {} provides the virtual package, but not so as to satisfy {:?}", name, installed, virt);
+                }
+                return
+            }
+        };
+        println!("-> installed {} {} does not satisfy {:?}", name, installed, self.rel2str(alt));
+        let available = match self.candidate_versions(package_num).into_iter().find(|v| self.version_satisfies(alt, v)) {
+            Some(v) => v,
+            None => match self.available_debvers.get(&package_num) {
+                Some(_) => return println!("-> and the available version {} does not either", self.candidate_versions(package_num).iter().format(", ")),
+                None => return println!("-> and no other version is available")
+            }
+        };
+        let upgraded = |pn: i32| if pn == package_num { Some(available) } else { self.installed_debvers.get(&pn) };
+        let mut blocked = false;
+        for (rdep, kind, _) in self.direct_rdepends(package_num, true) {
+            if kind != DependencyKind::PreDepends && kind != DependencyKind::Depends {
+                continue
+            }
+            let broken = self.dependencies_of_kind(kind, rdep).into_iter().flatten()
+                .filter(|rd| self.dependency_applies(rd) && self.dep_is_satisfied(rd).is_some() && !self.goal_satisfied_with(rd, &upgraded));
+            for rd in broken {
+                blocked = true;
+                println!("-> upgrading {} to {} would break installed {}, which {} {:?}", name, available, self.get_package_name(rdep), kind.verb(), self.dep2str(rd));
+            }
+        }
+        if !blocked {
+            println!("-> upgrading {} to {} would satisfy it", name, available);
+        }
+    }
+
+    /// Explains, alternative by alternative, why none of dep's alternatives is installed.
+    fn explain_not_installed(&self, dep: &Dependency) {
+        for alt in dep.iter().filter(|alt| self.alternative_applies(alt)) {
+            let name = self.get_package_name(alt.package_num);
+            let providers = self.providers_satisfying(alt);
+            match self.available_debvers.get(&alt.package_num) {
+                None if !providers.is_empty() =>
+                    println!("-> {} is a virtual package, and none of its providers is installed", name),
+                None => println!("-> {} is not available", name),
+                Some(_) if !self.arch_satisfies(alt, alt.package_num) =>
+                    println!("-> {} is for architecture {}", name, self.architectures.get(&alt.package_num).map_or("?", |a| a.as_str())),
+                Some(_) if !self.candidate_versions(alt.package_num).iter().any(|v| self.version_satisfies(alt, v)) =>
+                    println!("-> {} is only available at version {}", name, self.candidate_versions(alt.package_num).iter().format(", ")),
+                Some(av) => println!("-> {} {} is available, but not installed", name, av)
+            }
+        }
+    }
+}
@@ -0,0 +1,291 @@
+// Conformance corpus for DebianVersionNum's ordering: every expected result below is what
+// `dpkg --compare-versions` (dpkg 1.21) says about the pair.
+
+use std::cmp::Ordering::{self, Equal, Greater, Less};
+
+use rpkg::debversion::{self, DebianVersionNum, VersionConstraint, VersionRelation};
+
+const PAIRS : &[(&str, Ordering, &str)] = &[
+    // equality and spelling
+    ("0", Equal, "0"),
+    ("0", Equal, "00"),
+    ("1.0", Equal, "1.00"),
+    ("1.0", Equal, "01.0"),
+    ("1.0", Equal, "0:1.0"),
+    ("0:1.0", Equal, "00:1.0"),
+    ("1.0", Equal, "1.0-0"),
+    ("1.0-0", Equal, "1.0-00"),
+    ("1:2-3", Equal, "1:2-3"),
+    ("2.5.0", Equal, "2.5.0"),
+    ("1.0+dfsg-1", Equal, "1.0+dfsg-1"),
+    ("1.0~rc1", Equal, "1.0~rc1"),
+    ("1.0-1", Equal, "1.0-01"),
+    // epochs
+    ("0:0-0", Less, "1:0-0"),
+    ("1:1-1", Greater, "0:1-1"),
+    ("1:1.0", Greater, "2.0"),
+    ("2:0.1", Greater, "1:9.9"),
+    ("1:0", Greater, "0:99999"),
+    ("10:1", Greater, "9:1"),
+    ("1:1.0-1", Less, "1:1.0-2"),
+    ("3:1.0~rc1", Greater, "2:1.0"),
+    ("0:1.0", Less, "1.0-1"),
+    // upstream digits
+    ("1", Less, "2"),
+    ("9", Less, "10"),
+    ("1.2", Less, "1.10"),
+    ("1.9", Less, "1.10"),
+    ("1.01", Equal, "1.1"),
+    ("1.001", Equal, "1.1"),
+    ("0.9", Less, "0.10"),
+    ("1.2.3", Less, "1.2.3.4"),
+    ("1.2.3", Less, "1.2.4"),
+    ("2.10", Greater, "2.9"),
+    ("20030824", Less, "20030825"),
+    ("20220101", Greater, "20211231"),
+    ("1.20220101", Less, "1.20220101.1"),
+    ("4294967296", Greater, "4294967295"),
+    ("4294967296", Less, "4294967297"),
+    ("99999999999999999999", Greater, "99999999999999999998"),
+    ("1.99999999999999999999", Less, "1.100000000000000000000"),
+    ("123456789012345678901234567890", Less, "123456789012345678901234567891"),
+    ("1.0.20240101123456", Greater, "1.0.20240101123455"),
+    ("0000000000000000000001", Equal, "1"),
+    ("00000000000000000000010", Greater, "9"),
+    // upstream letters and punctuation
+    ("1a", Less, "1b"),
+    ("1a", Greater, "1"),
+    ("1.0a", Greater, "1.0"),
+    ("1.0a", Less, "1.0.1"),
+    ("1.0.a", Greater, "1.0a"),
+    ("1.0+", Less, "1.0."),
+    ("1.0+a", Less, "1.0.a"),
+    ("1.0-a", Less, "1.0+a"),
+    ("a", Less, "b"),
+    ("1A", Less, "1a"),
+    ("1Z", Less, "1a"),
+    ("1.0z", Less, "1.0+"),
+    ("1.0+b1", Greater, "1.0"),
+    ("1.0.1", Greater, "1.0+1"),
+    ("1.0+dfsg", Greater, "1.0"),
+    ("1.0+dfsg", Less, "1.0+dfsg1"),
+    ("1.0+dfsg1", Less, "1.0+dfsg2"),
+    ("2.0b3", Greater, "2.0"),
+    ("2.0b3", Less, "2.0b10"),
+    ("1.0cvs20030824", Less, "1.0cvs20030825"),
+    ("0.4.5+cvs20030824", Less, "0.4.5+cvs20030825"),
+    ("1.0.", Greater, "1.0"),
+    ("1.0+", Greater, "1.0"),
+    ("1.0a1", Less, "1.0a1.1"),
+    ("1.0aa", Less, "1.0ab"),
+    ("1.0ab", Less, "1.0b"),
+    ("1.0~a", Less, "1.0a"),
+    // tilde
+    ("1.0~rc1", Less, "1.0"),
+    ("1.0~rc1", Less, "1.0~rc2"),
+    ("1.0~rc10", Greater, "1.0~rc9"),
+    ("1.0~~", Less, "1.0~"),
+    ("1.0~~a", Less, "1.0~"),
+    ("1.0~", Less, "1.0"),
+    ("1.0~", Less, "1.0~a"),
+    ("1.0~beta", Less, "1.0~rc"),
+    ("1.0~rc1~git1", Less, "1.0~rc1"),
+    ("1.0~rc1", Less, "1.0~rc1+b1"),
+    ("1.0~1", Less, "1.0~a"),
+    ("~", Less, "0"),
+    ("0~", Less, "0"),
+    ("0~~", Less, "0~"),
+    ("1.0~0", Equal, "1.0~"),
+    ("1~~a", Greater, "1~~"),
+    ("1~a~b", Less, "1~a"),
+    ("1.2~3", Less, "1.2"),
+    ("1.2~3", Greater, "1.1"),
+    ("1.0~dfsg", Less, "1.0+dfsg"),
+    ("1.0~rc1-1", Less, "1.0-1"),
+    ("1.0-1~bpo1", Less, "1.0-1"),
+    ("1.0-1~bpo1", Less, "1.0-1+b1"),
+    ("1.0-1~deb11u1", Less, "1.0-1~deb12u1"),
+    ("1.0-1~exp1", Less, "1.0-1~exp2"),
+    // revisions
+    ("1.0-1", Less, "1.0-2"),
+    ("1.0-9", Less, "1.0-10"),
+    ("1.0-1", Less, "1.0-1.1"),
+    ("1.0-1.1", Greater, "1.0-1+b1"),
+    ("1.0-1", Less, "1.0-1+b1"),
+    ("1.0-1ubuntu1", Greater, "1.0-1"),
+    ("1.0-1ubuntu1", Less, "1.0-2"),
+    ("1.0-a", Less, "1.0-b"),
+    ("1:0.4.5+cvs20030824-9", Less, "1:0.4.5+cvs20030824-10"),
+    ("1-a", Less, "1-b"),
+    ("2-a", Greater, "1-b"),
+    ("1.0-1-1", Greater, "1.0-1"),
+    ("1.0-1-2", Less, "1.0-1-10"),
+    ("1.0-0.1", Less, "1.0-1"),
+    ("1.0-1", Greater, "1.0"),
+    ("1.0-0.0", Greater, "1.0"),
+    ("1.0-20220101", Less, "1.0-20220101.1"),
+    ("1.0-99999999999999999999", Less, "1.0-100000000000000000000"),
+    // real-world
+    ("2.33-1", Greater, "2.31-13+deb11u5"),
+    ("2.36-9+deb12u4", Less, "2.36-9+deb12u10"),
+    ("1:1.2.11.dfsg-2", Less, "1:1.2.13.dfsg-1"),
+    ("1:1.2.13.dfsg-1", Less, "1:1.3.dfsg-3"),
+    ("5.10.0-26", Less, "5.10.0-28"),
+    ("6.1.0-13", Greater, "6.1.0-9"),
+    ("3.0.11-1~deb12u2", Less, "3.0.11-1"),
+    ("1.1.1n-0+deb11u5", Less, "1.1.1w-0+deb11u1"),
+    ("7.88.1-10+deb12u5", Less, "7.88.1-10+deb12u12"),
+    ("2:8.2.2434-3+deb11u1", Less, "2:9.0.1378-2"),
+    ("0.0~git20200101.abc1234-1", Less, "0.0~git20200202.def5678-1"),
+    ("0.0~git20200101.abc1234-1", Less, "0.1-1"),
+    ("1.2.3+ds1-1", Greater, "1.2.3-1"),
+    ("1.2.3+really1.2.2-1", Less, "1.2.4-1"),
+    ("1:7.4.052-1ubuntu3", Less, "1:7.4.052-1ubuntu3.1"),
+    ("2.2.5-3", Less, "2.2.5-3+b1"),
+    ("10.2.1-6", Less, "12.2.0-14"),
+    ("1.9.6p1-1", Greater, "1.9.6-1"),
+    ("4.3-7", Less, "4.3a-1"),
+    ("3.7.2-5", Less, "3.7.2-5.1"),
+    ("1:2.38.1-5+b1", Less, "1:2.38.1-5+deb12u1"),
+    ("252.22-1~deb12u1", Greater, "252.19-1~deb12u1"),
+    ("1.0.2+git20221011+ds-1", Greater, "1.0.2-1"),
+];
+
+// parse_lenient, so that the corpus can also hold versions like "a" which policy doesn't allow
+fn v(s: &str) -> DebianVersionNum {
+    DebianVersionNum::parse_lenient(s).unwrap()
+}
+
+#[test]
+fn compares_like_dpkg() {
+    let mut failures = vec![];
+    for (a, expected, b) in PAIRS {
+        let got = v(a).cmp(&v(b));
+        if got != *expected {
+            failures.push(format!("{} vs {}: expected {:?}, got {:?}", a, b, expected, got));
+        }
+    }
+    assert!(failures.is_empty(), "{} of {} pairs compare wrongly:\n{}", failures.len(), PAIRS.len(), failures.join("\n"));
+}
+
+#[test]
+fn ordering_is_antisymmetric() {
+    for (a, expected, b) in PAIRS {
+        assert_eq!(v(b).cmp(&v(a)), expected.reverse(), "{} vs {}", b, a);
+        assert_eq!(v(a) == v(b), *expected == Equal, "{} == {}", a, b);
+    }
+}
+
+#[test]
+fn ordering_is_total() {
+    // if sorting the whole corpus leaves any pair out of order, the ordering isn't transitive
+    let mut versions : Vec<DebianVersionNum> = PAIRS.iter().flat_map(|(a, _, b)| [v(a), v(b)]).collect();
+    versions.sort();
+    for (i, a) in versions.iter().enumerate() {
+        for b in &versions[i..] {
+            assert!(a <= b, "{} sorts before {}", a, b);
+        }
+    }
+}
+
+#[test]
+fn relations_use_the_ordering() {
+    let cases = [
+        ("1.0", VersionRelation::StrictlyLess, "1.0+b1", true),
+        ("1.0~rc1", VersionRelation::StrictlyLess, "1.0", true),
+        ("1.0", VersionRelation::LessOrEqual, "1.00", true),
+        ("1.0", VersionRelation::Equal, "0:1.0-0", true),
+        ("1.0", VersionRelation::Equal, "1.0-1", false),
+        ("20230101", VersionRelation::GreaterOrEqual, "4294967296", false),
+        ("1.10", VersionRelation::StrictlyGreater, "1.9", true),
+        ("1.10", VersionRelation::StrictlyGreater, "1.10", false),
+    ];
+    for (a, op, b, expected) in cases {
+        assert_eq!(debversion::cmp_debversion_with_op(&op, &v(a), &v(b)), expected, "{} {} {}", a, op, b);
+    }
+}
+
+fn constraint(op: VersionRelation, s: &str) -> VersionConstraint {
+    VersionConstraint::new(op, v(s))
+}
+
+#[test]
+fn constraints_intersect_and_unite() {
+    let at_least_1_2 = constraint(VersionRelation::GreaterOrEqual, "1.2");
+    let below_2 = constraint(VersionRelation::StrictlyLess, "2.0");
+    let range = at_least_1_2.intersection(&below_2);
+    assert_eq!(range.to_string(), ">= 1.2, << 2.0");
+    assert!(range.satisfied_by(&v("1.2")) && range.satisfied_by(&v("2.0~rc1")));
+    assert!(!range.satisfied_by(&v("1.2~")) && !range.satisfied_by(&v("2.0")));
+
+    assert!(range.intersection(&constraint(VersionRelation::StrictlyGreater, "2.0")).is_empty());
+    assert!(range.intersection(&constraint(VersionRelation::GreaterOrEqual, "2.0")).is_empty());
+    assert!(constraint(VersionRelation::LessOrEqual, "2.0").intersection(&constraint(VersionRelation::StrictlyGreater, "2.0")).is_empty());
+    let point = constraint(VersionRelation::LessOrEqual, "2.0").intersection(&constraint(VersionRelation::GreaterOrEqual, "2.00"));
+    assert!(point.exact_version().is_some_and(|p| *p == v("2.0")));
+    assert_eq!(range.intersection(&VersionConstraint::any()).to_string(), range.to_string());
+    assert!(range.intersection(&VersionConstraint::none()).is_empty());
+
+    let outside = constraint(VersionRelation::StrictlyLess, "1.2").union(&constraint(VersionRelation::GreaterOrEqual, "2.0"));
+    assert_eq!(outside.to_string(), "<< 1.2 | >= 2.0");
+    assert!(outside.intersection(&range).is_empty());
+    assert_eq!(outside.union(&range).to_string(), "any");
+    // << 1.2 and >> 1.2 leave a hole at exactly 1.2
+    assert_eq!(constraint(VersionRelation::StrictlyLess, "1.2").union(&constraint(VersionRelation::StrictlyGreater, "1.2")).to_string(), "<< 1.2 | >> 1.2");
+}
+
+#[test]
+fn single_relations_print_as_written() {
+    for (op, s) in [(VersionRelation::StrictlyLess, "<< 1:2.0-1"), (VersionRelation::LessOrEqual, "<= 1.0"), (VersionRelation::Equal, "= 1.0~rc1"),
+                    (VersionRelation::GreaterOrEqual, ">= 2.30"), (VersionRelation::StrictlyGreater, ">> 0")] {
+        let version = s.split_once(' ').unwrap().1;
+        assert_eq!(constraint(op, version).to_string(), s);
+    }
+    assert_eq!(constraint(VersionRelation::Equal, "1.0").exact_version().map(|v| v.to_string()), Some(String::from("1.0")));
+    assert!(constraint(VersionRelation::GreaterOrEqual, "1.0").exact_version().is_none());
+}
+
+// Debian policy rules for version numbers: each bad version below breaks one, and validate has to say which.
+const POLICY : &[(&str, Option<&str>)] = &[
+    ("1.0", None),
+    ("1:2.0~rc1+dfsg-1.1", None),
+    ("2.0-1-2", None),
+    ("0:0", None),
+    ("", Some("empty version")),
+    ("1.0 1", Some("whitespace")),
+    ("1.0\t", Some("whitespace")),
+    (":1.0", Some("epoch is empty")),
+    ("x:1.0", Some("epoch \"x\" is not a number")),
+    ("-1:1.0", Some("epoch \"-1\" is not a number")),
+    ("99999999999:1.0", Some("epoch \"99999999999\" is too big")),
+    ("1:", Some("upstream version is empty")),
+    ("a1.0", None),
+    ("1:.5", None),
+    ("1.0_1", Some("illegal character '_' in upstream version")),
+    ("1.0:1", Some("epoch \"1.0\" is not a number")),
+    ("1.0-", Some("Debian revision is empty")),
+    ("1.0-1_2", Some("illegal character '_' in Debian revision")),
+    ("1.0-1:2", Some("epoch \"1.0-1\" is not a number")),
+];
+
+#[test]
+fn validate_names_the_broken_rule() {
+    for (version, rule) in POLICY {
+        match (DebianVersionNum::validate(version), rule) {
+            (Ok(()), None) => (),
+            (Ok(()), Some(rule)) => panic!("{:?} should break the rule {:?}", version, rule),
+            (Err(e), None) => panic!("{:?} should be valid, but: {}", version, e),
+            (Err(e), Some(rule)) => assert!(e.to_string().contains(rule), "{:?}: expected {:?}, got {}", version, rule, e)
+        }
+        // strict parsing holds versions to the same rules
+        assert_eq!(version.parse::<DebianVersionNum>().is_ok(), rule.is_none(), "{:?}", version);
+    }
+}
+
+#[test]
+fn warns_about_an_upstream_version_not_starting_with_a_digit() {
+    assert_eq!(DebianVersionNum::warning("a1.0").as_deref(), Some("upstream version \"a1.0\" does not start with a digit"));
+    assert_eq!(DebianVersionNum::warning("1:.5").as_deref(), Some("upstream version \".5\" does not start with a digit"));
+    assert_eq!(DebianVersionNum::warning("1:2.0-a1"), None);
+    assert_eq!(DebianVersionNum::warning(""), None);
+}